@@ -1,6 +1,22 @@
-mod arm32;
-mod thumb32;
-pub use self::{
-    arm32::*,
-    thumb32::*,
-};
+mod arm32;
+mod assembly;
+mod disassembly;
+mod encoding;
+#[cfg(test)]
+mod test_words;
+mod thumb32;
+pub use self::{
+    arm32::*,
+    assembly::{AssemblyError, AssemblyResult},
+    thumb32::*,
+};
+
+/// The version of the ARM architecture that instructions are decoded for.
+/// Newer versions have more instructions, so they can be compared.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ArmArchitecture {
+    /// The ARM7TDMI (GBA)
+    V4T,
+    /// The ARM946E-S (NDS), which adds BLX, CLZ, the saturating arithmetic and DSP multiplies
+    V5TE,
+}
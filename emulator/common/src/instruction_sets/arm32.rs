@@ -1,103 +1,1112 @@
-pub enum Arm32 {
-    Branch(Branch),
-}
-impl Arm32 {
-    pub fn find_instruction(instruction: u32) -> Option<Arm32> {
-        if let Some(branch) = Branch::from_instruction(instruction) {
-            Some(Arm32::Branch(branch))
-        } else {
-            None
-        }
-    }
-}
-
-pub struct Branch {
-    pub condition: Condition,
-    /// If the condition is full, it's a BLX (switch to Thumb and this is the half-word offset).
-    /// If not, false is branch and true is branch with link
-    pub opcode: bool,
-    /// The offset to the new instruction in bytes
-    pub offset: i32,
-}
-impl Branch {
-    fn from_instruction(instruction: u32) -> Option<Branch> {
-        const IDENTIFIER: u32 = 0b00001010_00000000_00000000_00000000;
-        const OPCODE_BIT: u32 = 0b00000001_00000000_00000000_00000000;
-        const OFFSET_BYTES: u32 = 0b00000000_11111111_11111111_11111111;
-        const OFFSET_SIGNED_BIT: u32 = 0b00000000_10000000_00000000_00000000;
-        const OFFSET_SIGNED_EXTRA: u32 = 0b11111111_00000000_00000000_00000000;
-        if instruction & IDENTIFIER == IDENTIFIER {
-            let condition = Condition::from_instruction(instruction);
-            let opcode = instruction & OPCODE_BIT == OPCODE_BIT;
-            let mut offset = instruction & OFFSET_BYTES;
-            if offset & OFFSET_SIGNED_BIT == OFFSET_SIGNED_BIT {
-                // The offset is supposed to be negative so fill them out before converting
-                offset |= OFFSET_SIGNED_EXTRA;
-            }
-            let offset = offset as i32 * 4;
-            Some(Branch { condition, opcode, offset })
-        } else {
-            None
-        }
-    }
-}
-
-#[derive(Copy, Clone, Eq, PartialEq)]
-pub enum Condition {
-    /// Flags: Z=1, equal (zero) (same)
-    Equal,
-    /// Flags: Z=0, not equal (nonzero) (not same)
-    NEqual,
-    /// Flags: C=1, unsigned higher or same (carry set)
-    CarrySet,
-    /// Flags: C=0, unsigned lower (carry cleared)
-    CarryClear,
-    /// Flags: N=1, signed negative (minus)
-    Minus,
-    /// Flags: N=0, signed positive or zero (plus)
-    Positive,
-    /// Flags: V=1, signed overflow (V set)
-    VSet,
-    /// Flags: V=0, signed no overflow (V cleared)
-    VClear,
-    /// Flags: C=1 and Z=0, unsigned higher
-    UHigh,
-    /// Flags: C=0 or Z=1, unsigned lower or same
-    ULow,
-    /// Flags: N=V, signed greater or equal
-    GreaterEqual,
-    /// Flags: N<>V, signed less than
-    Less,
-    /// Flags: Z=0 and N=V, signed greater than
-    Greater,
-    /// Flags: Z=1 or N<>V, signed less or equal
-    LessEqual,
-    /// Flags: None, always (the "AL" suffix can be omitted)
-    Always,
-    /// Flags: None, never (ARMv1,v2 only) (Reserved ARMv3 and up)
-    AllSet,
-}
-impl Condition {
-    fn from_instruction(instruction: u32) -> Condition {
-        // We only want to match against the first 4 bits
-        match instruction & 0xF000_0000 {
-            0x0000_0000 => Condition::Equal,
-            0x1000_0000 => Condition::NEqual,
-            0x2000_0000 => Condition::CarrySet,
-            0x3000_0000 => Condition::CarryClear,
-            0x4000_0000 => Condition::Minus,
-            0x5000_0000 => Condition::Positive,
-            0x6000_0000 => Condition::VSet,
-            0x7000_0000 => Condition::VClear,
-            0x8000_0000 => Condition::UHigh,
-            0x9000_0000 => Condition::ULow,
-            0xA000_0000 => Condition::GreaterEqual,
-            0xB000_0000 => Condition::Less,
-            0xC000_0000 => Condition::Greater,
-            0xD000_0000 => Condition::LessEqual,
-            0xE000_0000 => Condition::Always,
-            0xF000_0000 => Condition::AllSet,
-            _ => panic!("Programming error"),
-        }
-    }
-}
+use super::ArmArchitecture;
+
+pub enum Arm32 {
+    Branch(Branch),
+    DataProcessing(DataProcessing),
+    SingleDataTransfer(SingleDataTransfer),
+    HalfwordDataTransfer(HalfwordDataTransfer),
+    BlockDataTransfer(BlockDataTransfer),
+    Multiply(Multiply),
+    MultiplyLong(MultiplyLong),
+    StatusToRegister(StatusToRegister),
+    RegisterToStatus(RegisterToStatus),
+    BranchExchange(BranchExchange),
+    Swap(Swap),
+    SoftwareInterrupt(SoftwareInterrupt),
+
+    // ARMv5TE only
+    CountLeadingZeros(CountLeadingZeros),
+    SaturatingArithmetic(SaturatingArithmetic),
+    SignedHalfwordMultiply(SignedHalfwordMultiply),
+}
+impl Arm32 {
+    /// Only the instructions that exist in the architecture will be found
+    pub fn find_instruction(instruction: u32, architecture: ArmArchitecture) -> Option<Arm32> {
+        let table = match architecture {
+            ArmArchitecture::V4T => &V4T_DECODE_TABLE,
+            ArmArchitecture::V5TE => &V5TE_DECODE_TABLE,
+        };
+        // Bits 27-20 and 7-4 narrow it down to the few classes that could match
+        let index = ((instruction >> 16) & 0xFF0) | ((instruction >> 4) & 0xF);
+        let mut classes = table[index as usize];
+        while classes != 0 {
+            let class = &DECODE_CLASSES[classes.trailing_zeros() as usize];
+            classes &= classes - 1;
+            if let Some(decoded) = (class.decode)(instruction, architecture) {
+                return Some(decoded);
+            }
+        }
+        None
+    }
+}
+
+/// The classes that could match each index, as bits into `DECODE_CLASSES`
+static V4T_DECODE_TABLE: [u16; 4096] = build_decode_table(false);
+static V5TE_DECODE_TABLE: [u16; 4096] = build_decode_table(true);
+
+struct DecodeClass {
+    identifier_mask: u32,
+    identifier: u32,
+    /// If the class only exists from ARMv5TE
+    v5te_only: bool,
+    decode: fn(u32, ArmArchitecture) -> Option<Arm32>,
+}
+
+/// Every class, in the order that they get tried (some encodings overlap)
+const DECODE_CLASSES: [DecodeClass; 15] = [
+    DecodeClass {
+        identifier_mask: Branch::IDENTIFIER_MASK,
+        identifier: Branch::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, architecture| Branch::from_instruction(instruction, architecture)
+            .map(Arm32::Branch),
+    },
+    DecodeClass {
+        identifier_mask: DataProcessing::IDENTIFIER_MASK,
+        identifier: DataProcessing::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| DataProcessing::from_instruction(instruction)
+            .map(Arm32::DataProcessing),
+    },
+    DecodeClass {
+        identifier_mask: SingleDataTransfer::IDENTIFIER_MASK,
+        identifier: SingleDataTransfer::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| SingleDataTransfer::from_instruction(instruction)
+            .map(Arm32::SingleDataTransfer),
+    },
+    DecodeClass {
+        identifier_mask: HalfwordDataTransfer::IDENTIFIER_MASK,
+        identifier: HalfwordDataTransfer::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| HalfwordDataTransfer::from_instruction(instruction)
+            .map(Arm32::HalfwordDataTransfer),
+    },
+    DecodeClass {
+        identifier_mask: BlockDataTransfer::IDENTIFIER_MASK,
+        identifier: BlockDataTransfer::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| BlockDataTransfer::from_instruction(instruction)
+            .map(Arm32::BlockDataTransfer),
+    },
+    DecodeClass {
+        identifier_mask: Multiply::IDENTIFIER_MASK,
+        identifier: Multiply::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| Multiply::from_instruction(instruction).map(Arm32::Multiply),
+    },
+    DecodeClass {
+        identifier_mask: MultiplyLong::IDENTIFIER_MASK,
+        identifier: MultiplyLong::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| MultiplyLong::from_instruction(instruction)
+            .map(Arm32::MultiplyLong),
+    },
+    DecodeClass {
+        identifier_mask: StatusToRegister::IDENTIFIER_MASK,
+        identifier: StatusToRegister::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| StatusToRegister::from_instruction(instruction)
+            .map(Arm32::StatusToRegister),
+    },
+    DecodeClass {
+        identifier_mask: RegisterToStatus::IDENTIFIER_MASK,
+        identifier: RegisterToStatus::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| RegisterToStatus::from_instruction(instruction)
+            .map(Arm32::RegisterToStatus),
+    },
+    DecodeClass {
+        identifier_mask: BranchExchange::IDENTIFIER_MASK,
+        identifier: BranchExchange::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, architecture| {
+            BranchExchange::from_instruction(instruction, architecture).map(Arm32::BranchExchange)
+        },
+    },
+    DecodeClass {
+        identifier_mask: Swap::IDENTIFIER_MASK,
+        identifier: Swap::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| Swap::from_instruction(instruction).map(Arm32::Swap),
+    },
+    DecodeClass {
+        identifier_mask: SoftwareInterrupt::IDENTIFIER_MASK,
+        identifier: SoftwareInterrupt::IDENTIFIER,
+        v5te_only: false,
+        decode: |instruction, _| SoftwareInterrupt::from_instruction(instruction)
+            .map(Arm32::SoftwareInterrupt),
+    },
+    DecodeClass {
+        identifier_mask: CountLeadingZeros::IDENTIFIER_MASK,
+        identifier: CountLeadingZeros::IDENTIFIER,
+        v5te_only: true,
+        decode: |instruction, _| CountLeadingZeros::from_instruction(instruction)
+            .map(Arm32::CountLeadingZeros),
+    },
+    DecodeClass {
+        identifier_mask: SaturatingArithmetic::IDENTIFIER_MASK,
+        identifier: SaturatingArithmetic::IDENTIFIER,
+        v5te_only: true,
+        decode: |instruction, _| SaturatingArithmetic::from_instruction(instruction)
+            .map(Arm32::SaturatingArithmetic),
+    },
+    DecodeClass {
+        identifier_mask: SignedHalfwordMultiply::IDENTIFIER_MASK,
+        identifier: SignedHalfwordMultiply::IDENTIFIER,
+        v5te_only: true,
+        decode: |instruction, _| SignedHalfwordMultiply::from_instruction(instruction)
+            .map(Arm32::SignedHalfwordMultiply),
+    },
+];
+
+const fn build_decode_table(has_v5te: bool) -> [u16; 4096] {
+    const INDEX_BITS: u32 = 0b00001111_11110000_00000000_11110000;
+    let mut table = [0; 4096];
+    let mut index = 0;
+    while index < table.len() {
+        // Put the index bits back where they came from
+        let instruction = ((index as u32 & 0xFF0) << 16) | ((index as u32 & 0xF) << 4);
+        let mut class = 0;
+        while class < DECODE_CLASSES.len() {
+            // Only the index bits can be checked here, so the rest is left to the decoder
+            let mask = DECODE_CLASSES[class].identifier_mask & INDEX_BITS;
+            if instruction & mask == DECODE_CLASSES[class].identifier & mask &&
+                (has_v5te || !DECODE_CLASSES[class].v5te_only) {
+                table[index] |= 1 << class;
+            }
+            class += 1;
+        }
+        index += 1;
+    }
+    table
+}
+
+pub struct Branch {
+    pub condition: Condition,
+    /// If the condition is full, it's a BLX (switch to Thumb and this is the half-word offset).
+    /// BLX only exists from ARMv5.
+    /// If not, false is branch and true is branch with link
+    pub opcode: bool,
+    /// The offset to the new instruction in bytes
+    pub offset: i32,
+}
+impl Branch {
+    const IDENTIFIER_MASK: u32 = 0b00001110_00000000_00000000_00000000;
+    const IDENTIFIER: u32 = 0b00001010_00000000_00000000_00000000;
+
+    fn from_instruction(instruction: u32, architecture: ArmArchitecture) -> Option<Branch> {
+        const OPCODE_BIT: u32 = 0b00000001_00000000_00000000_00000000;
+        const OFFSET_BYTES: u32 = 0b00000000_11111111_11111111_11111111;
+        const OFFSET_SIGNED_BIT: u32 = 0b00000000_10000000_00000000_00000000;
+        const OFFSET_SIGNED_EXTRA: u32 = 0b11111111_00000000_00000000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            let condition = Condition::from_instruction(instruction);
+            if condition == Condition::AllSet && architecture < ArmArchitecture::V5TE {
+                return None;
+            }
+            let opcode = instruction & OPCODE_BIT == OPCODE_BIT;
+            let mut offset = instruction & OFFSET_BYTES;
+            if offset & OFFSET_SIGNED_BIT == OFFSET_SIGNED_BIT {
+                // The offset is supposed to be negative so fill them out before converting
+                offset |= OFFSET_SIGNED_EXTRA;
+            }
+            let offset = offset as i32 * 4;
+            Some(Branch { condition, opcode, offset })
+        } else {
+            None
+        }
+    }
+}
+
+/// BX: Branch to the address in a register, switching to Thumb if bit 0 is set
+pub struct BranchExchange {
+    pub condition: Condition,
+    /// If the return address goes into LR (BLX, ARMv5 only)
+    pub link: bool,
+    /// The register with the address
+    pub rn: u8,
+}
+impl BranchExchange {
+    const IDENTIFIER_MASK: u32 = 0b00001111_11111111_11111111_11010000;
+    const IDENTIFIER: u32 = 0b00000001_00101111_11111111_00010000;
+
+    fn from_instruction(instruction: u32, architecture: ArmArchitecture)
+    -> Option<BranchExchange> {
+        const LINK_BIT: u32 = 0b00000000_00000000_00000000_00100000;
+        if instruction & Self::IDENTIFIER_MASK != Self::IDENTIFIER {
+            return None;
+        }
+
+        let link = instruction & LINK_BIT == LINK_BIT;
+        if link && architecture < ArmArchitecture::V5TE {
+            return None;
+        }
+        Some(BranchExchange {
+            condition: Condition::from_instruction(instruction),
+            link,
+            rn: (instruction & 0xF) as u8,
+        })
+    }
+}
+
+pub struct DataProcessing {
+    pub condition: Condition,
+    pub opcode: AluOpcode,
+    /// If the N, Z, C and V flags should be updated from the result
+    pub set_condition_codes: bool,
+    /// The register for the 1st operand (unused by MOV and MVN)
+    pub rn: u8,
+    /// The destination register (unused by TST, TEQ, CMP and CMN)
+    pub rd: u8,
+    pub operand2: ShifterOperand,
+}
+impl DataProcessing {
+    const IDENTIFIER_MASK: u32 = 0b00001100_00000000_00000000_00000000;
+    const IDENTIFIER: u32 = 0;
+
+    fn from_instruction(instruction: u32) -> Option<DataProcessing> {
+        const IMMEDIATE_BIT: u32 = 0b00000010_00000000_00000000_00000000;
+        const OPCODE_BITS: u32 = 0b00000001_11100000_00000000_00000000;
+        const SET_CONDITION_BIT: u32 = 0b00000000_00010000_00000000_00000000;
+        // Multiplies, swaps and halfword transfers live in the register form with bits 7 and 4 set
+        const MULTIPLY_MASK: u32 = 0b00000010_00000000_00000000_10010000;
+        const MULTIPLY_IDENTIFIER: u32 = 0b00000000_00000000_00000000_10010000;
+        if instruction & Self::IDENTIFIER_MASK != Self::IDENTIFIER ||
+            instruction & MULTIPLY_MASK == MULTIPLY_IDENTIFIER {
+            return None;
+        }
+
+        let opcode = AluOpcode::from_bits((instruction & OPCODE_BITS) >> 21);
+        let set_condition_codes = instruction & SET_CONDITION_BIT == SET_CONDITION_BIT;
+        if opcode.is_test() && !set_condition_codes {
+            // These are the PSR transfers and branch exchange instead
+            return None;
+        }
+
+        let operand2 = if instruction & IMMEDIATE_BIT == IMMEDIATE_BIT {
+            ShifterOperand::Immediate {
+                value: (instruction & 0xFF) as u8,
+                rotate: ((instruction >> 8) & 0xF) as u8 * 2,
+            }
+        } else {
+            ShifterOperand::Register {
+                rm: (instruction & 0xF) as u8,
+                shift: Shift::from_instruction(instruction),
+            }
+        };
+        Some(DataProcessing {
+            condition: Condition::from_instruction(instruction),
+            opcode,
+            set_condition_codes,
+            rn: ((instruction >> 16) & 0xF) as u8,
+            rd: ((instruction >> 12) & 0xF) as u8,
+            operand2,
+        })
+    }
+}
+
+pub struct SingleDataTransfer {
+    pub condition: Condition,
+    /// If the offset is applied before the transfer (pre-indexing) or after (post-indexing)
+    pub pre_index: bool,
+    /// If the offset is added to the base (up) or subtracted from it (down)
+    pub up: bool,
+    /// If a single byte is transferred instead of a word
+    pub byte: bool,
+    /// If the final address is written back to the base register.
+    /// Post-indexing always writes back, where this means a forced user mode access instead.
+    pub write_back: bool,
+    /// If this is a load from memory (LDR) or a store to memory (STR)
+    pub load: bool,
+    /// The base register
+    pub rn: u8,
+    /// The source or destination register
+    pub rd: u8,
+    pub offset: TransferOffset,
+}
+impl SingleDataTransfer {
+    const IDENTIFIER_MASK: u32 = 0b00001100_00000000_00000000_00000000;
+    const IDENTIFIER: u32 = 0b00000100_00000000_00000000_00000000;
+
+    fn from_instruction(instruction: u32) -> Option<SingleDataTransfer> {
+        const REGISTER_OFFSET_BIT: u32 = 0b00000010_00000000_00000000_00000000;
+        const PRE_INDEX_BIT: u32 = 0b00000001_00000000_00000000_00000000;
+        const UP_BIT: u32 = 0b00000000_10000000_00000000_00000000;
+        const BYTE_BIT: u32 = 0b00000000_01000000_00000000_00000000;
+        const WRITE_BACK_BIT: u32 = 0b00000000_00100000_00000000_00000000;
+        const LOAD_BIT: u32 = 0b00000000_00010000_00000000_00000000;
+        // A register offset with bit 4 set is the undefined instruction space
+        const UNDEFINED_MASK: u32 = 0b00000010_00000000_00000000_00010000;
+        if instruction & Self::IDENTIFIER_MASK != Self::IDENTIFIER ||
+            instruction & UNDEFINED_MASK == UNDEFINED_MASK {
+            return None;
+        }
+
+        let offset = if instruction & REGISTER_OFFSET_BIT == REGISTER_OFFSET_BIT {
+            TransferOffset::Register {
+                rm: (instruction & 0xF) as u8,
+                shift: Shift::from_instruction(instruction),
+            }
+        } else {
+            TransferOffset::Immediate((instruction & 0xFFF) as u16)
+        };
+        Some(SingleDataTransfer {
+            condition: Condition::from_instruction(instruction),
+            pre_index: instruction & PRE_INDEX_BIT == PRE_INDEX_BIT,
+            up: instruction & UP_BIT == UP_BIT,
+            byte: instruction & BYTE_BIT == BYTE_BIT,
+            write_back: instruction & WRITE_BACK_BIT == WRITE_BACK_BIT,
+            load: instruction & LOAD_BIT == LOAD_BIT,
+            rn: ((instruction >> 16) & 0xF) as u8,
+            rd: ((instruction >> 12) & 0xF) as u8,
+            offset,
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum TransferOffset {
+    Immediate(u16),
+    /// The shift amount is always an immediate here
+    Register { rm: u8, shift: Shift },
+}
+
+pub struct HalfwordDataTransfer {
+    pub condition: Condition,
+    /// If the offset is applied before the transfer (pre-indexing) or after (post-indexing)
+    pub pre_index: bool,
+    /// If the offset is added to the base (up) or subtracted from it (down)
+    pub up: bool,
+    /// If the final address is written back to the base register (post-indexing always does)
+    pub write_back: bool,
+    /// If this is a load from memory or a store to memory (only unsigned halfwords can be stored)
+    pub load: bool,
+    /// The base register
+    pub rn: u8,
+    /// The source or destination register
+    pub rd: u8,
+    pub transfer_type: HalfwordTransferType,
+    pub offset: HalfwordOffset,
+}
+impl HalfwordDataTransfer {
+    const IDENTIFIER_MASK: u32 = 0b00001110_00000000_00000000_10010000;
+    const IDENTIFIER: u32 = 0b00000000_00000000_00000000_10010000;
+
+    fn from_instruction(instruction: u32) -> Option<HalfwordDataTransfer> {
+        const PRE_INDEX_BIT: u32 = 0b00000001_00000000_00000000_00000000;
+        const UP_BIT: u32 = 0b00000000_10000000_00000000_00000000;
+        const IMMEDIATE_BIT: u32 = 0b00000000_01000000_00000000_00000000;
+        const WRITE_BACK_BIT: u32 = 0b00000000_00100000_00000000_00000000;
+        const LOAD_BIT: u32 = 0b00000000_00010000_00000000_00000000;
+        const REGISTER_ZERO_BITS: u32 = 0b00000000_00000000_00001111_00000000;
+        if instruction & Self::IDENTIFIER_MASK != Self::IDENTIFIER {
+            return None;
+        }
+
+        let load = instruction & LOAD_BIT == LOAD_BIT;
+        // The S and H bits being both clear is where the multiplies and swaps live
+        let transfer_type = match ((instruction >> 5) & 0b11, load) {
+            (0b01, _) => HalfwordTransferType::Halfword,
+            (0b10, true) => HalfwordTransferType::SignedByte,
+            (0b11, true) => HalfwordTransferType::SignedHalfword,
+            _ => return None,
+        };
+        let offset = if instruction & IMMEDIATE_BIT == IMMEDIATE_BIT {
+            HalfwordOffset::Immediate((((instruction >> 4) & 0xF0) | (instruction & 0xF)) as u8)
+        } else if instruction & REGISTER_ZERO_BITS == 0 {
+            HalfwordOffset::Register((instruction & 0xF) as u8)
+        } else {
+            return None;
+        };
+        Some(HalfwordDataTransfer {
+            condition: Condition::from_instruction(instruction),
+            pre_index: instruction & PRE_INDEX_BIT == PRE_INDEX_BIT,
+            up: instruction & UP_BIT == UP_BIT,
+            write_back: instruction & WRITE_BACK_BIT == WRITE_BACK_BIT,
+            load,
+            rn: ((instruction >> 16) & 0xF) as u8,
+            rd: ((instruction >> 12) & 0xF) as u8,
+            transfer_type,
+            offset,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum HalfwordTransferType {
+    /// LDRH/STRH: Unsigned halfword
+    Halfword,
+    /// LDRSB: Sign extended byte
+    SignedByte,
+    /// LDRSH: Sign extended halfword
+    SignedHalfword,
+}
+
+#[derive(Copy, Clone)]
+pub enum HalfwordOffset {
+    Immediate(u8),
+    /// The offset register (no shift is possible)
+    Register(u8),
+}
+
+pub struct BlockDataTransfer {
+    pub condition: Condition,
+    /// If the address is moved before each transfer (IB/DB) or after each transfer (IA/DA)
+    pub pre_index: bool,
+    /// If the addresses go up from the base (IA/IB) or down from it (DA/DB)
+    pub up: bool,
+    /// Loads with R15 in the list restore the CPSR from the SPSR.
+    /// Otherwise the user bank registers are transferred instead of the current mode's.
+    pub psr_or_user: bool,
+    /// If the final address is written back to the base register
+    pub write_back: bool,
+    /// If this is a load from memory (LDM) or a store to memory (STM)
+    pub load: bool,
+    /// The base register
+    pub rn: u8,
+    /// Each set bit is a register to transfer, with bit 0 being R0
+    pub register_list: u16,
+}
+impl BlockDataTransfer {
+    const IDENTIFIER_MASK: u32 = 0b00001110_00000000_00000000_00000000;
+    const IDENTIFIER: u32 = 0b00001000_00000000_00000000_00000000;
+
+    fn from_instruction(instruction: u32) -> Option<BlockDataTransfer> {
+        const PRE_INDEX_BIT: u32 = 0b00000001_00000000_00000000_00000000;
+        const UP_BIT: u32 = 0b00000000_10000000_00000000_00000000;
+        const PSR_OR_USER_BIT: u32 = 0b00000000_01000000_00000000_00000000;
+        const WRITE_BACK_BIT: u32 = 0b00000000_00100000_00000000_00000000;
+        const LOAD_BIT: u32 = 0b00000000_00010000_00000000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(BlockDataTransfer {
+                condition: Condition::from_instruction(instruction),
+                pre_index: instruction & PRE_INDEX_BIT == PRE_INDEX_BIT,
+                up: instruction & UP_BIT == UP_BIT,
+                psr_or_user: instruction & PSR_OR_USER_BIT == PSR_OR_USER_BIT,
+                write_back: instruction & WRITE_BACK_BIT == WRITE_BACK_BIT,
+                load: instruction & LOAD_BIT == LOAD_BIT,
+                rn: ((instruction >> 16) & 0xF) as u8,
+                register_list: (instruction & 0xFFFF) as u16,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Multiply {
+    pub condition: Condition,
+    /// If Rn is added to the product (MLA) or not (MUL)
+    pub accumulate: bool,
+    /// If the N and Z flags should be updated from the result
+    pub set_condition_codes: bool,
+    /// The destination register
+    pub rd: u8,
+    /// The register to accumulate
+    pub rn: u8,
+    /// The multiplier register (its value decides how long the multiply takes)
+    pub rs: u8,
+    pub rm: u8,
+}
+impl Multiply {
+    const IDENTIFIER_MASK: u32 = 0b00001111_11000000_00000000_11110000;
+    const IDENTIFIER: u32 = 0b00000000_00000000_00000000_10010000;
+
+    fn from_instruction(instruction: u32) -> Option<Multiply> {
+        const ACCUMULATE_BIT: u32 = 0b00000000_00100000_00000000_00000000;
+        const SET_CONDITION_BIT: u32 = 0b00000000_00010000_00000000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(Multiply {
+                condition: Condition::from_instruction(instruction),
+                accumulate: instruction & ACCUMULATE_BIT == ACCUMULATE_BIT,
+                set_condition_codes: instruction & SET_CONDITION_BIT == SET_CONDITION_BIT,
+                rd: ((instruction >> 16) & 0xF) as u8,
+                rn: ((instruction >> 12) & 0xF) as u8,
+                rs: ((instruction >> 8) & 0xF) as u8,
+                rm: (instruction & 0xF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct MultiplyLong {
+    pub condition: Condition,
+    /// If the operands are signed (SMULL/SMLAL) or unsigned (UMULL/UMLAL)
+    pub signed: bool,
+    /// If RdHi:RdLo is added to the product
+    pub accumulate: bool,
+    /// If the N and Z flags should be updated from the 64 bit result
+    pub set_condition_codes: bool,
+    /// The destination register for the top 32 bits
+    pub rd_hi: u8,
+    /// The destination register for the bottom 32 bits
+    pub rd_lo: u8,
+    /// The multiplier register (its value decides how long the multiply takes)
+    pub rs: u8,
+    pub rm: u8,
+}
+impl MultiplyLong {
+    const IDENTIFIER_MASK: u32 = 0b00001111_10000000_00000000_11110000;
+    const IDENTIFIER: u32 = 0b00000000_10000000_00000000_10010000;
+
+    fn from_instruction(instruction: u32) -> Option<MultiplyLong> {
+        const SIGNED_BIT: u32 = 0b00000000_01000000_00000000_00000000;
+        const ACCUMULATE_BIT: u32 = 0b00000000_00100000_00000000_00000000;
+        const SET_CONDITION_BIT: u32 = 0b00000000_00010000_00000000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(MultiplyLong {
+                condition: Condition::from_instruction(instruction),
+                signed: instruction & SIGNED_BIT == SIGNED_BIT,
+                accumulate: instruction & ACCUMULATE_BIT == ACCUMULATE_BIT,
+                set_condition_codes: instruction & SET_CONDITION_BIT == SET_CONDITION_BIT,
+                rd_hi: ((instruction >> 16) & 0xF) as u8,
+                rd_lo: ((instruction >> 12) & 0xF) as u8,
+                rs: ((instruction >> 8) & 0xF) as u8,
+                rm: (instruction & 0xF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// MRS: Moves a program status register into a register
+pub struct StatusToRegister {
+    pub condition: Condition,
+    /// If the SPSR of the current mode is read instead of the CPSR
+    pub spsr: bool,
+    /// The destination register
+    pub rd: u8,
+}
+impl StatusToRegister {
+    const IDENTIFIER_MASK: u32 = 0b00001111_10111111_00001111_11111111;
+    const IDENTIFIER: u32 = 0b00000001_00001111_00000000_00000000;
+
+    fn from_instruction(instruction: u32) -> Option<StatusToRegister> {
+        const SPSR_BIT: u32 = 0b00000000_01000000_00000000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(StatusToRegister {
+                condition: Condition::from_instruction(instruction),
+                spsr: instruction & SPSR_BIT == SPSR_BIT,
+                rd: ((instruction >> 12) & 0xF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// MSR: Moves a register or an immediate into a program status register
+pub struct RegisterToStatus {
+    pub condition: Condition,
+    /// If the SPSR of the current mode is written instead of the CPSR
+    pub spsr: bool,
+    /// Which bytes of the PSR get written. Bit 0 is the control byte (bits 7-0),
+    /// up to bit 3 which is the flags byte (bits 31-24).
+    pub field_mask: u8,
+    pub operand: StatusOperand,
+}
+impl RegisterToStatus {
+    const IDENTIFIER_MASK: u32 = 0b00001101_10110000_11110000_00000000;
+    const IDENTIFIER: u32 = 0b00000001_00100000_11110000_00000000;
+
+    fn from_instruction(instruction: u32) -> Option<RegisterToStatus> {
+        const IMMEDIATE_BIT: u32 = 0b00000010_00000000_00000000_00000000;
+        const REGISTER_ZERO_BITS: u32 = 0b00000000_00000000_00001111_11110000;
+        const SPSR_BIT: u32 = 0b00000000_01000000_00000000_00000000;
+        if instruction & Self::IDENTIFIER_MASK != Self::IDENTIFIER {
+            return None;
+        }
+
+        let operand = if instruction & IMMEDIATE_BIT == IMMEDIATE_BIT {
+            StatusOperand::Immediate {
+                value: (instruction & 0xFF) as u8,
+                rotate: ((instruction >> 8) & 0xF) as u8 * 2,
+            }
+        } else if instruction & REGISTER_ZERO_BITS == 0 {
+            StatusOperand::Register((instruction & 0xF) as u8)
+        } else {
+            return None;
+        };
+        Some(RegisterToStatus {
+            condition: Condition::from_instruction(instruction),
+            spsr: instruction & SPSR_BIT == SPSR_BIT,
+            field_mask: ((instruction >> 16) & 0xF) as u8,
+            operand,
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum StatusOperand {
+    /// The value is rotated right by the rotate amount (always even)
+    Immediate { value: u8, rotate: u8 },
+    Register(u8),
+}
+
+/// SWP: Atomically loads from memory and stores a register to that same address
+pub struct Swap {
+    pub condition: Condition,
+    /// If a single byte is swapped instead of a word
+    pub byte: bool,
+    /// The register with the address
+    pub rn: u8,
+    /// The destination register
+    pub rd: u8,
+    /// The source register
+    pub rm: u8,
+}
+impl Swap {
+    const IDENTIFIER_MASK: u32 = 0b00001111_10110000_00001111_11110000;
+    const IDENTIFIER: u32 = 0b00000001_00000000_00000000_10010000;
+
+    fn from_instruction(instruction: u32) -> Option<Swap> {
+        const BYTE_BIT: u32 = 0b00000000_01000000_00000000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(Swap {
+                condition: Condition::from_instruction(instruction),
+                byte: instruction & BYTE_BIT == BYTE_BIT,
+                rn: ((instruction >> 16) & 0xF) as u8,
+                rd: ((instruction >> 12) & 0xF) as u8,
+                rm: (instruction & 0xF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// SWI: Enters Supervisor mode through the software interrupt vector
+pub struct SoftwareInterrupt {
+    pub condition: Condition,
+    /// Ignored by the CPU, but the handler can read it back from the instruction
+    pub comment: u32,
+}
+impl SoftwareInterrupt {
+    const IDENTIFIER_MASK: u32 = 0b00001111_00000000_00000000_00000000;
+    const IDENTIFIER: u32 = 0b00001111_00000000_00000000_00000000;
+
+    fn from_instruction(instruction: u32) -> Option<SoftwareInterrupt> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(SoftwareInterrupt {
+                condition: Condition::from_instruction(instruction),
+                comment: instruction & 0x00FF_FFFF,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// CLZ: Counts the zero bits above the highest set bit
+pub struct CountLeadingZeros {
+    pub condition: Condition,
+    /// The destination register
+    pub rd: u8,
+    pub rm: u8,
+}
+impl CountLeadingZeros {
+    const IDENTIFIER_MASK: u32 = 0b00001111_11111111_00001111_11110000;
+    const IDENTIFIER: u32 = 0b00000001_01101111_00001111_00010000;
+
+    fn from_instruction(instruction: u32) -> Option<CountLeadingZeros> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(CountLeadingZeros {
+                condition: Condition::from_instruction(instruction),
+                rd: ((instruction >> 12) & 0xF) as u8,
+                rm: (instruction & 0xF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// QADD, QSUB, QDADD and QDSUB: Signed arithmetic that saturates instead of overflowing
+pub struct SaturatingArithmetic {
+    pub condition: Condition,
+    pub opcode: SaturatingOpcode,
+    /// The 2nd operand (doubled first by QDADD and QDSUB)
+    pub rn: u8,
+    /// The destination register
+    pub rd: u8,
+    /// The 1st operand
+    pub rm: u8,
+}
+impl SaturatingArithmetic {
+    const IDENTIFIER_MASK: u32 = 0b00001111_10010000_00001111_11110000;
+    const IDENTIFIER: u32 = 0b00000001_00000000_00000000_01010000;
+
+    fn from_instruction(instruction: u32) -> Option<SaturatingArithmetic> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            let opcode = match (instruction >> 21) & 0b11 {
+                0b00 => SaturatingOpcode::Add,
+                0b01 => SaturatingOpcode::Subtract,
+                0b10 => SaturatingOpcode::DoubleAdd,
+                0b11 => SaturatingOpcode::DoubleSubtract,
+                _ => panic!("Programming error"),
+            };
+            Some(SaturatingArithmetic {
+                condition: Condition::from_instruction(instruction),
+                opcode,
+                rn: ((instruction >> 16) & 0xF) as u8,
+                rd: ((instruction >> 12) & 0xF) as u8,
+                rm: (instruction & 0xF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum SaturatingOpcode {
+    /// QADD: Rd = SAT(Rm + Rn)
+    Add,
+    /// QSUB: Rd = SAT(Rm - Rn)
+    Subtract,
+    /// QDADD: Rd = SAT(Rm + SAT(Rn * 2))
+    DoubleAdd,
+    /// QDSUB: Rd = SAT(Rm - SAT(Rn * 2))
+    DoubleSubtract,
+}
+
+/// SMLAxy, SMLAWy, SMULWy, SMLALxy and SMULxy: Multiplies that work on signed halfwords
+pub struct SignedHalfwordMultiply {
+    pub condition: Condition,
+    pub opcode: HalfwordMultiplyOpcode,
+    /// The destination register (RdHi for SMLALxy)
+    pub rd: u8,
+    /// The register to accumulate (RdLo for SMLALxy)
+    pub rn: u8,
+    /// The register with the y halfword
+    pub rs: u8,
+    /// The register with the x halfword (the full word for SMLAWy and SMULWy)
+    pub rm: u8,
+    /// If the top halfword of Rm is used instead of the bottom one
+    pub x_top: bool,
+    /// If the top halfword of Rs is used instead of the bottom one
+    pub y_top: bool,
+}
+impl SignedHalfwordMultiply {
+    const IDENTIFIER_MASK: u32 = 0b00001111_10010000_00000000_10010000;
+    const IDENTIFIER: u32 = 0b00000001_00000000_00000000_10000000;
+
+    fn from_instruction(instruction: u32) -> Option<SignedHalfwordMultiply> {
+        const X_BIT: u32 = 0b00000000_00000000_00000000_00100000;
+        const Y_BIT: u32 = 0b00000000_00000000_00000000_01000000;
+        if instruction & Self::IDENTIFIER_MASK != Self::IDENTIFIER {
+            return None;
+        }
+
+        let x_top = instruction & X_BIT == X_BIT;
+        let opcode = match ((instruction >> 21) & 0b11, x_top) {
+            (0b00, _) => HalfwordMultiplyOpcode::MultiplyAccumulate,
+            // The x bit picks the word multiply instead
+            (0b01, false) => HalfwordMultiplyOpcode::MultiplyAccumulateWord,
+            (0b01, true) => HalfwordMultiplyOpcode::MultiplyWord,
+            (0b10, _) => HalfwordMultiplyOpcode::MultiplyAccumulateLong,
+            (0b11, _) => HalfwordMultiplyOpcode::Multiply,
+            _ => panic!("Programming error"),
+        };
+        let is_word = opcode == HalfwordMultiplyOpcode::MultiplyAccumulateWord ||
+            opcode == HalfwordMultiplyOpcode::MultiplyWord;
+        Some(SignedHalfwordMultiply {
+            condition: Condition::from_instruction(instruction),
+            opcode,
+            rd: ((instruction >> 16) & 0xF) as u8,
+            rn: ((instruction >> 12) & 0xF) as u8,
+            rs: ((instruction >> 8) & 0xF) as u8,
+            rm: (instruction & 0xF) as u8,
+            x_top: x_top && !is_word,
+            y_top: instruction & Y_BIT == Y_BIT,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum HalfwordMultiplyOpcode {
+    /// SMLAxy: Rd = HalfRm * HalfRs + Rn
+    MultiplyAccumulate,
+    /// SMLAWy: Rd = (Rm * HalfRs) >> 16 + Rn
+    MultiplyAccumulateWord,
+    /// SMULWy: Rd = (Rm * HalfRs) >> 16
+    MultiplyWord,
+    /// SMLALxy: RdHi:RdLo = RdHi:RdLo + HalfRm * HalfRs
+    MultiplyAccumulateLong,
+    /// SMULxy: Rd = HalfRm * HalfRs
+    Multiply,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum AluOpcode {
+    /// AND: Rd = Rn AND Op2
+    And,
+    /// EOR: Rd = Rn XOR Op2
+    ExclusiveOr,
+    /// SUB: Rd = Rn - Op2
+    Subtract,
+    /// RSB: Rd = Op2 - Rn
+    ReverseSubtract,
+    /// ADD: Rd = Rn + Op2
+    Add,
+    /// ADC: Rd = Rn + Op2 + C
+    AddCarry,
+    /// SBC: Rd = Rn - Op2 + C - 1
+    SubtractCarry,
+    /// RSC: Rd = Op2 - Rn + C - 1
+    ReverseSubtractCarry,
+    /// TST: Void = Rn AND Op2
+    Test,
+    /// TEQ: Void = Rn XOR Op2
+    TestEqual,
+    /// CMP: Void = Rn - Op2
+    Compare,
+    /// CMN: Void = Rn + Op2
+    CompareNegative,
+    /// ORR: Rd = Rn OR Op2
+    Or,
+    /// MOV: Rd = Op2
+    Move,
+    /// BIC: Rd = Rn AND NOT Op2
+    BitClear,
+    /// MVN: Rd = NOT Op2
+    MoveNot,
+}
+impl AluOpcode {
+    fn from_bits(bits: u32) -> AluOpcode {
+        match bits {
+            0x0 => AluOpcode::And,
+            0x1 => AluOpcode::ExclusiveOr,
+            0x2 => AluOpcode::Subtract,
+            0x3 => AluOpcode::ReverseSubtract,
+            0x4 => AluOpcode::Add,
+            0x5 => AluOpcode::AddCarry,
+            0x6 => AluOpcode::SubtractCarry,
+            0x7 => AluOpcode::ReverseSubtractCarry,
+            0x8 => AluOpcode::Test,
+            0x9 => AluOpcode::TestEqual,
+            0xA => AluOpcode::Compare,
+            0xB => AluOpcode::CompareNegative,
+            0xC => AluOpcode::Or,
+            0xD => AluOpcode::Move,
+            0xE => AluOpcode::BitClear,
+            0xF => AluOpcode::MoveNot,
+            _ => panic!("Programming error"),
+        }
+    }
+
+    /// Test opcodes only set the flags and never write to Rd
+    pub fn is_test(self) -> bool {
+        matches!(self, AluOpcode::Test | AluOpcode::TestEqual |
+            AluOpcode::Compare | AluOpcode::CompareNegative)
+    }
+    /// Logical opcodes take their carry from the shifter and leave V alone
+    pub fn is_logical(self) -> bool {
+        matches!(self,
+            AluOpcode::And | AluOpcode::ExclusiveOr | AluOpcode::Test | AluOpcode::TestEqual |
+            AluOpcode::Or | AluOpcode::Move | AluOpcode::BitClear | AluOpcode::MoveNot)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum ShifterOperand {
+    /// The value is rotated right by the rotate amount (always even)
+    Immediate { value: u8, rotate: u8 },
+    Register { rm: u8, shift: Shift },
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Shift {
+    pub shift_type: ShiftType,
+    pub amount: ShiftAmount,
+}
+impl Shift {
+    /// Reads the shift out of bits 11-4.
+    /// The special immediate encodings (LSR #0, ASR #0 and ROR #0) are turned into what they mean.
+    fn from_instruction(instruction: u32) -> Shift {
+        const REGISTER_SHIFT_BIT: u32 = 0b00000000_00000000_00000000_00010000;
+        let shift_type_bits = (instruction >> 5) & 0b11;
+        if instruction & REGISTER_SHIFT_BIT == REGISTER_SHIFT_BIT {
+            let rs = ((instruction >> 8) & 0xF) as u8;
+            Shift {
+                shift_type: ShiftType::from_bits(shift_type_bits),
+                amount: ShiftAmount::Register(rs),
+            }
+        } else {
+            Shift::from_immediate(shift_type_bits, ((instruction >> 7) & 0b11111) as u8)
+        }
+    }
+
+    /// Makes a shift by an immediate (5 bits), which Thumb shares
+    pub(crate) fn from_immediate(shift_type_bits: u32, amount: u8) -> Shift {
+        let shift_type = ShiftType::from_bits(shift_type_bits);
+        match (shift_type, amount) {
+            (ShiftType::LogicalRight, 0) | (ShiftType::ArithmeticRight, 0) =>
+                Shift { shift_type, amount: ShiftAmount::Immediate(32) },
+            (ShiftType::RotateRight, 0) =>
+                Shift { shift_type: ShiftType::RotateRightExtended, amount: ShiftAmount::Immediate(1) },
+            _ => Shift { shift_type, amount: ShiftAmount::Immediate(amount) },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ShiftType {
+    /// LSL
+    LogicalLeft,
+    /// LSR
+    LogicalRight,
+    /// ASR
+    ArithmeticRight,
+    /// ROR
+    RotateRight,
+    /// RRX: Rotate right by 1 through the carry flag
+    RotateRightExtended,
+}
+impl ShiftType {
+    pub(crate) fn from_bits(bits: u32) -> ShiftType {
+        match bits {
+            0b00 => ShiftType::LogicalLeft,
+            0b01 => ShiftType::LogicalRight,
+            0b10 => ShiftType::ArithmeticRight,
+            0b11 => ShiftType::RotateRight,
+            _ => panic!("Programming error"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ShiftAmount {
+    Immediate(u8),
+    /// The bottom byte of this register is the amount
+    Register(u8),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Condition {
+    /// Flags: Z=1, equal (zero) (same)
+    Equal,
+    /// Flags: Z=0, not equal (nonzero) (not same)
+    NEqual,
+    /// Flags: C=1, unsigned higher or same (carry set)
+    CarrySet,
+    /// Flags: C=0, unsigned lower (carry cleared)
+    CarryClear,
+    /// Flags: N=1, signed negative (minus)
+    Minus,
+    /// Flags: N=0, signed positive or zero (plus)
+    Positive,
+    /// Flags: V=1, signed overflow (V set)
+    VSet,
+    /// Flags: V=0, signed no overflow (V cleared)
+    VClear,
+    /// Flags: C=1 and Z=0, unsigned higher
+    UHigh,
+    /// Flags: C=0 or Z=1, unsigned lower or same
+    ULow,
+    /// Flags: N=V, signed greater or equal
+    GreaterEqual,
+    /// Flags: N<>V, signed less than
+    Less,
+    /// Flags: Z=0 and N=V, signed greater than
+    Greater,
+    /// Flags: Z=1 or N<>V, signed less or equal
+    LessEqual,
+    /// Flags: None, always (the "AL" suffix can be omitted)
+    Always,
+    /// Flags: None, never (ARMv1,v2 only) (Reserved ARMv3 and up)
+    AllSet,
+}
+impl Condition {
+    fn from_instruction(instruction: u32) -> Condition {
+        // We only want to match against the first 4 bits
+        Condition::from_bits(instruction >> 28)
+    }
+
+    pub(crate) fn from_bits(bits: u32) -> Condition {
+        match bits {
+            0x0 => Condition::Equal,
+            0x1 => Condition::NEqual,
+            0x2 => Condition::CarrySet,
+            0x3 => Condition::CarryClear,
+            0x4 => Condition::Minus,
+            0x5 => Condition::Positive,
+            0x6 => Condition::VSet,
+            0x7 => Condition::VClear,
+            0x8 => Condition::UHigh,
+            0x9 => Condition::ULow,
+            0xA => Condition::GreaterEqual,
+            0xB => Condition::Less,
+            0xC => Condition::Greater,
+            0xD => Condition::LessEqual,
+            0xE => Condition::Always,
+            0xF => Condition::AllSet,
+            _ => panic!("Programming error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::discriminant;
+    use super::super::test_words::random_words;
+    use super::*;
+
+    /// The linear decoder from before the table, which the table has to match exactly
+    fn find_instruction_linear(instruction: u32, architecture: ArmArchitecture) -> Option<Arm32> {
+        if let Some(branch) = Branch::from_instruction(instruction, architecture) {
+            Some(Arm32::Branch(branch))
+        } else if let Some(data_processing) = DataProcessing::from_instruction(instruction) {
+            Some(Arm32::DataProcessing(data_processing))
+        } else if let Some(transfer) = SingleDataTransfer::from_instruction(instruction) {
+            Some(Arm32::SingleDataTransfer(transfer))
+        } else if let Some(transfer) = HalfwordDataTransfer::from_instruction(instruction) {
+            Some(Arm32::HalfwordDataTransfer(transfer))
+        } else if let Some(transfer) = BlockDataTransfer::from_instruction(instruction) {
+            Some(Arm32::BlockDataTransfer(transfer))
+        } else if let Some(multiply) = Multiply::from_instruction(instruction) {
+            Some(Arm32::Multiply(multiply))
+        } else if let Some(multiply) = MultiplyLong::from_instruction(instruction) {
+            Some(Arm32::MultiplyLong(multiply))
+        } else if let Some(transfer) = StatusToRegister::from_instruction(instruction) {
+            Some(Arm32::StatusToRegister(transfer))
+        } else if let Some(transfer) = RegisterToStatus::from_instruction(instruction) {
+            Some(Arm32::RegisterToStatus(transfer))
+        } else if let Some(branch) = BranchExchange::from_instruction(instruction, architecture) {
+            Some(Arm32::BranchExchange(branch))
+        } else if let Some(swap) = Swap::from_instruction(instruction) {
+            Some(Arm32::Swap(swap))
+        } else if let Some(interrupt) = SoftwareInterrupt::from_instruction(instruction) {
+            Some(Arm32::SoftwareInterrupt(interrupt))
+        } else if architecture < ArmArchitecture::V5TE {
+            None
+        } else if let Some(count) = CountLeadingZeros::from_instruction(instruction) {
+            Some(Arm32::CountLeadingZeros(count))
+        } else if let Some(arithmetic) = SaturatingArithmetic::from_instruction(instruction) {
+            Some(Arm32::SaturatingArithmetic(arithmetic))
+        } else {
+            SignedHalfwordMultiply::from_instruction(instruction)
+                .map(Arm32::SignedHalfwordMultiply)
+        }
+    }
+
+    #[test]
+    fn table_matches_linear_decoder() {
+        // Every index of the table, with a few different fillings of the other bits
+        for (index, filling) in (0..4096 * 16).zip(random_words(4096 * 16)) {
+            let index_bits = ((index & 0xFF0) << 16) | ((index & 0xF) << 4);
+            let instruction = (filling & !0x0FF0_00F0) | index_bits;
+            for architecture in [ArmArchitecture::V4T, ArmArchitecture::V5TE] {
+                let decoded = Arm32::find_instruction(instruction, architecture)
+                    .map(|decoded| (discriminant(&decoded), decoded.encode()));
+                let expected = find_instruction_linear(instruction, architecture)
+                    .map(|expected| (discriminant(&expected), expected.encode()));
+                assert!(decoded == expected, "{:08X} ({:?})", instruction, architecture);
+            }
+        }
+    }
+}
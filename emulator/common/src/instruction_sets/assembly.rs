@@ -0,0 +1,1014 @@
+use super::{
+    arm32::*,
+    thumb32::*,
+};
+
+pub type AssemblyResult<T> = Result<T, AssemblyError>;
+#[derive(Clone, Debug)]
+pub enum AssemblyError {
+    /// The mnemonic (with its suffixes) isn't one that we know
+    UnknownMnemonic(String),
+    /// The operands don't fit with the mnemonic
+    InvalidOperands(String),
+    /// A value can't fit into the instruction
+    OutOfRange(String),
+}
+
+impl Arm32 {
+    /// Reads a line of assembly, using the same syntax that `disassemble` gives back.
+    /// The address is where this instruction will live, so that branch targets can be resolved.
+    pub fn assemble(text: &str, address: u32) -> AssemblyResult<Arm32> {
+        const FORMS: &[&str] = &[
+            "b", "bl", "bx", "blx",
+            "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc", "orr", "mov", "bic", "mvn",
+            "ands", "eors", "subs", "rsbs", "adds", "adcs", "sbcs", "rscs", "orrs", "movs", "bics",
+            "mvns", "tst", "teq", "cmp", "cmn",
+            "ldr", "str", "ldrb", "strb", "ldrt", "strt", "ldrbt", "strbt",
+            "ldrh", "strh", "ldrsb", "ldrsh",
+            "ldm", "stm", "ldmia", "ldmib", "ldmda", "ldmdb", "stmia", "stmib", "stmda", "stmdb",
+            "push", "pop",
+            "mul", "muls", "mla", "mlas",
+            "umull", "umulls", "umlal", "umlals", "smull", "smulls", "smlal", "smlals",
+            "mrs", "msr", "swp", "swpb", "swi", "clz", "qadd", "qsub", "qdadd", "qdsub",
+            "smlabb", "smlabt", "smlatb", "smlatt", "smlawb", "smlawt", "smulwb", "smulwt",
+            "smlalbb", "smlalbt", "smlaltb", "smlaltt", "smulbb", "smulbt", "smultb", "smultt",
+        ];
+        let (mnemonic, operands) = split_line(text);
+        // The condition goes after everything else, so look for the form that leaves one behind
+        let (form, condition) = FORMS.iter()
+            .filter(|form| mnemonic.starts_with(*form))
+            .filter_map(|form| {
+                parse_condition(&mnemonic[form.len()..]).map(|condition| (*form, condition))
+            })
+            .max_by_key(|(form, _)| form.len())
+            .ok_or_else(|| AssemblyError::UnknownMnemonic(mnemonic.clone()))?;
+        let operands: Vec<&str> = operands.iter().map(|operand| operand.as_str()).collect();
+
+        let instruction = match form {
+            "b" | "bl" | "blx" if operands.len() == 1 && parse_register(operands[0]).is_err() => {
+                let offset = parse_number(operands[0])? as i64 - (address as i64 + 8);
+                if !(-0x0200_0000..0x0200_0000).contains(&offset) {
+                    return Err(AssemblyError::OutOfRange(text.to_string()));
+                }
+                if form == "blx" {
+                    // The half-word goes into the opcode bit
+                    if condition != Condition::Always || offset & 1 != 0 {
+                        return Err(AssemblyError::InvalidOperands(text.to_string()));
+                    }
+                    Arm32::Branch(Branch {
+                        condition: Condition::AllSet,
+                        opcode: offset & 2 != 0,
+                        offset: (offset & !0b11) as i32,
+                    })
+                } else {
+                    if offset & 0b11 != 0 {
+                        return Err(AssemblyError::InvalidOperands(text.to_string()));
+                    }
+                    Arm32::Branch(Branch { condition, opcode: form == "bl", offset: offset as i32 })
+                }
+            },
+            // Only BLX can take a register
+            "b" | "bl" => return Err(AssemblyError::InvalidOperands(text.to_string())),
+            "bx" | "blx" => {
+                expect_count(text, &operands, 1)?;
+                Arm32::BranchExchange(BranchExchange {
+                    condition,
+                    link: form == "blx",
+                    rn: parse_register(operands[0])?,
+                })
+            },
+            "ldr" | "str" | "ldrb" | "strb" | "ldrt" | "strt" | "ldrbt" | "strbt" => {
+                let (rd, address_mode) = parse_transfer(text, &operands)?;
+                let user = form.ends_with('t');
+                // The forced user mode access is always post-indexed, even without an offset
+                let pre_index = match address_mode.offset {
+                    AddressOffset::Immediate(0) if user && !address_mode.write_back => false,
+                    _ => address_mode.pre_index,
+                };
+                if user && pre_index {
+                    return Err(AssemblyError::InvalidOperands(text.to_string()));
+                }
+                let offset = match address_mode.offset {
+                    AddressOffset::Immediate(offset) if offset < 0x1000 =>
+                        TransferOffset::Immediate(offset as u16),
+                    AddressOffset::Immediate(_) =>
+                        return Err(AssemblyError::OutOfRange(text.to_string())),
+                    AddressOffset::Register { rm, shift } => TransferOffset::Register { rm, shift },
+                };
+                Arm32::SingleDataTransfer(SingleDataTransfer {
+                    condition,
+                    pre_index,
+                    up: address_mode.up,
+                    byte: form[3..].starts_with('b'),
+                    // Which is marked by the write back bit
+                    write_back: address_mode.write_back || user,
+                    load: form.starts_with("ldr"),
+                    rn: address_mode.rn,
+                    rd,
+                    offset,
+                })
+            },
+            "ldrh" | "strh" | "ldrsb" | "ldrsh" => {
+                let (rd, address_mode) = parse_transfer(text, &operands)?;
+                let offset = match address_mode.offset {
+                    AddressOffset::Immediate(offset) if offset < 0x100 =>
+                        HalfwordOffset::Immediate(offset as u8),
+                    AddressOffset::Register { rm, shift } if shift == NO_SHIFT =>
+                        HalfwordOffset::Register(rm),
+                    _ => return Err(AssemblyError::OutOfRange(text.to_string())),
+                };
+                let transfer_type = match form {
+                    "ldrsb" => HalfwordTransferType::SignedByte,
+                    "ldrsh" => HalfwordTransferType::SignedHalfword,
+                    _ => HalfwordTransferType::Halfword,
+                };
+                Arm32::HalfwordDataTransfer(HalfwordDataTransfer {
+                    condition,
+                    pre_index: address_mode.pre_index,
+                    up: address_mode.up,
+                    write_back: address_mode.write_back,
+                    load: form.starts_with("ldr"),
+                    rn: address_mode.rn,
+                    rd,
+                    transfer_type,
+                    offset,
+                })
+            },
+            "push" | "pop" => {
+                expect_count(text, &operands, 1)?;
+                let (register_list, psr_or_user) = parse_register_list(operands[0])?;
+                Arm32::BlockDataTransfer(BlockDataTransfer {
+                    condition,
+                    pre_index: form == "push",
+                    up: form == "pop",
+                    psr_or_user,
+                    write_back: true,
+                    load: form == "pop",
+                    rn: 13,
+                    register_list,
+                })
+            },
+            "mul" | "muls" | "mla" | "mlas" => {
+                let accumulate = form.starts_with("mla");
+                expect_count(text, &operands, if accumulate { 4 } else { 3 })?;
+                Arm32::Multiply(Multiply {
+                    condition,
+                    accumulate,
+                    set_condition_codes: form.ends_with('s'),
+                    rd: parse_register(operands[0])?,
+                    rn: if accumulate { parse_register(operands[3])? } else { 0 },
+                    rs: parse_register(operands[2])?,
+                    rm: parse_register(operands[1])?,
+                })
+            },
+            "mrs" => {
+                expect_count(text, &operands, 2)?;
+                Arm32::StatusToRegister(StatusToRegister {
+                    condition,
+                    spsr: match operands[1] {
+                        "cpsr" => false,
+                        "spsr" => true,
+                        _ => return Err(AssemblyError::InvalidOperands(text.to_string())),
+                    },
+                    rd: parse_register(operands[0])?,
+                })
+            },
+            "msr" => {
+                expect_count(text, &operands, 2)?;
+                let (status_register, fields) = match operands[0].find('_') {
+                    Some(index) => (&operands[0][..index], &operands[0][index + 1..]),
+                    None => (operands[0], "all"),
+                };
+                let spsr = match status_register {
+                    "cpsr" => false,
+                    "spsr" => true,
+                    _ => return Err(AssemblyError::InvalidOperands(text.to_string())),
+                };
+                let field_mask = if fields == "all" {
+                    0b1001
+                } else {
+                    let mut field_mask = 0;
+                    for field in fields.chars() {
+                        field_mask |= match field {
+                            'c' => 0b0001,
+                            'x' => 0b0010,
+                            's' => 0b0100,
+                            'f' => 0b1000,
+                            _ => return Err(AssemblyError::InvalidOperands(text.to_string())),
+                        };
+                    }
+                    field_mask
+                };
+                let operand = if operands[1].starts_with('#') {
+                    let (value, rotate) = encode_immediate(parse_immediate(operands[1])? as u32)
+                        .ok_or_else(|| AssemblyError::OutOfRange(text.to_string()))?;
+                    StatusOperand::Immediate { value, rotate }
+                } else {
+                    StatusOperand::Register(parse_register(operands[1])?)
+                };
+                Arm32::RegisterToStatus(RegisterToStatus { condition, spsr, field_mask, operand })
+            },
+            "swp" | "swpb" => {
+                expect_count(text, &operands, 3)?;
+                let rn = operands[2].strip_prefix('[').and_then(|rn| rn.strip_suffix(']'))
+                    .ok_or_else(|| AssemblyError::InvalidOperands(text.to_string()))?;
+                Arm32::Swap(Swap {
+                    condition,
+                    byte: form == "swpb",
+                    rn: parse_register(rn)?,
+                    rd: parse_register(operands[0])?,
+                    rm: parse_register(operands[1])?,
+                })
+            },
+            "swi" => {
+                expect_count(text, &operands, 1)?;
+                let comment = parse_immediate(operands[0])?;
+                if !(0..=0x00FF_FFFF).contains(&comment) {
+                    return Err(AssemblyError::OutOfRange(text.to_string()));
+                }
+                Arm32::SoftwareInterrupt(SoftwareInterrupt { condition, comment: comment as u32 })
+            },
+            "clz" => {
+                expect_count(text, &operands, 2)?;
+                Arm32::CountLeadingZeros(CountLeadingZeros {
+                    condition,
+                    rd: parse_register(operands[0])?,
+                    rm: parse_register(operands[1])?,
+                })
+            },
+            "qadd" | "qsub" | "qdadd" | "qdsub" => {
+                expect_count(text, &operands, 3)?;
+                Arm32::SaturatingArithmetic(SaturatingArithmetic {
+                    condition,
+                    opcode: match form {
+                        "qadd" => SaturatingOpcode::Add,
+                        "qsub" => SaturatingOpcode::Subtract,
+                        "qdadd" => SaturatingOpcode::DoubleAdd,
+                        _ => SaturatingOpcode::DoubleSubtract,
+                    },
+                    rn: parse_register(operands[2])?,
+                    rd: parse_register(operands[0])?,
+                    rm: parse_register(operands[1])?,
+                })
+            },
+            _ if form.starts_with("umul") || form.starts_with("umla") || form == "smull" ||
+                form == "smulls" || form == "smlal" || form == "smlals" => {
+                expect_count(text, &operands, 4)?;
+                Arm32::MultiplyLong(MultiplyLong {
+                    condition,
+                    signed: form.starts_with('s'),
+                    accumulate: form[1..].starts_with("mla"),
+                    set_condition_codes: form.ends_with('s'),
+                    rd_hi: parse_register(operands[1])?,
+                    rd_lo: parse_register(operands[0])?,
+                    rs: parse_register(operands[3])?,
+                    rm: parse_register(operands[2])?,
+                })
+            },
+            _ if form.starts_with("sml") || form.starts_with("smul") =>
+                assemble_halfword_multiply(text, form, condition, &operands)?,
+            _ if form.starts_with("ldm") || form.starts_with("stm") => {
+                expect_count(text, &operands, 2)?;
+                let (rn, write_back) = match operands[0].strip_suffix('!') {
+                    Some(rn) => (rn, true),
+                    None => (operands[0], false),
+                };
+                let (register_list, psr_or_user) = parse_register_list(operands[1])?;
+                let mode = if form.len() == 3 { "ia" } else { &form[3..] };
+                Arm32::BlockDataTransfer(BlockDataTransfer {
+                    condition,
+                    pre_index: mode.ends_with('b'),
+                    up: mode.starts_with('i'),
+                    psr_or_user,
+                    write_back,
+                    load: form.starts_with("ldm"),
+                    rn: parse_register(rn)?,
+                    register_list,
+                })
+            },
+            _ => assemble_data_processing(text, form, condition, &operands)?,
+        };
+        Ok(instruction)
+    }
+}
+
+impl Thumb32 {
+    /// Reads a line of assembly, using the same syntax that `disassemble` gives back.
+    /// The address is where this instruction will live, so that branch targets can be resolved.
+    /// BL is written as its 2 halves: `bl lr = <target>` then `bl lr + #<offset>`.
+    /// A whole `bl <target>` goes through `assemble_pair` instead.
+    pub fn assemble(text: &str, address: u32) -> AssemblyResult<Thumb32> {
+        let (mnemonic, operands) = split_line(text);
+        let operands: Vec<&str> = operands.iter().map(|operand| operand.as_str()).collect();
+        let invalid = || AssemblyError::InvalidOperands(text.to_string());
+        let out_of_range = || AssemblyError::OutOfRange(text.to_string());
+        // The PC is 2 instructions ahead
+        let pc = address as i64 + 4;
+
+        let instruction = match (mnemonic.as_str(), operands.len()) {
+            ("lsl", 3) | ("lsr", 3) | ("asr", 3) => {
+                let shift = parse_shift(&format!("{} {}", mnemonic, operands[2]))?;
+                Thumb32::MoveShiftedRegister(MoveShiftedRegister {
+                    shift,
+                    rs: parse_low_register(text, operands[1])?,
+                    rd: parse_low_register(text, operands[0])?,
+                })
+            },
+            ("add", 3) if operands[1] == "sp" || operands[1] == "pc" => {
+                let offset = parse_immediate(operands[2])?;
+                if !(0..=0x3FC).contains(&offset) || offset & 0b11 != 0 {
+                    return Err(out_of_range());
+                }
+                Thumb32::LoadAddress(LoadAddress {
+                    sp: operands[1] == "sp",
+                    rd: parse_low_register(text, operands[0])?,
+                    offset: offset as u16,
+                })
+            },
+            ("add", 3) | ("sub", 3) => {
+                let immediate = operands[2].starts_with('#');
+                let operand = if immediate {
+                    let operand = parse_immediate(operands[2])?;
+                    if !(0..=7).contains(&operand) {
+                        return Err(out_of_range());
+                    }
+                    operand as u8
+                } else {
+                    parse_low_register(text, operands[2])?
+                };
+                Thumb32::AddSubtract(AddSubtract {
+                    immediate,
+                    subtract: mnemonic == "sub",
+                    operand,
+                    rs: parse_low_register(text, operands[1])?,
+                    rd: parse_low_register(text, operands[0])?,
+                })
+            },
+            ("add", 2) | ("sub", 2) if operands[0] == "sp" && operands[1].starts_with('#') => {
+                let mut offset = parse_immediate(operands[1])?;
+                if mnemonic == "sub" {
+                    offset = -offset;
+                }
+                if offset.abs() > 0x1FC || offset & 0b11 != 0 {
+                    return Err(out_of_range());
+                }
+                Thumb32::AddOffsetToSp(AddOffsetToSp { offset: offset as i16 })
+            },
+            ("mov", 2) | ("cmp", 2) | ("add", 2) | ("sub", 2) if operands[1].starts_with('#') => {
+                let offset = parse_immediate(operands[1])?;
+                if !(0..=0xFF).contains(&offset) {
+                    return Err(out_of_range());
+                }
+                Thumb32::ImmediateOperation(ImmediateOperation {
+                    opcode: match mnemonic.as_str() {
+                        "mov" => ImmediateOpcode::Move,
+                        "cmp" => ImmediateOpcode::Compare,
+                        "add" => ImmediateOpcode::Add,
+                        _ => ImmediateOpcode::Subtract,
+                    },
+                    rd: parse_low_register(text, operands[0])?,
+                    offset: offset as u8,
+                })
+            },
+            ("mov", 2) | ("add", 2) | ("cmp", 2) => {
+                let rd = parse_register(operands[0])?;
+                let rs = parse_register(operands[1])?;
+                // Comparing 2 low registers is an ALU operation
+                if mnemonic == "cmp" && rd < 8 && rs < 8 {
+                    Thumb32::AluOperation(AluOperation { opcode: ThumbAluOpcode::Compare, rs, rd })
+                } else {
+                    Thumb32::HiRegisterOperation(HiRegisterOperation {
+                        opcode: match mnemonic.as_str() {
+                            "mov" => HiRegisterOpcode::Move,
+                            "add" => HiRegisterOpcode::Add,
+                            _ => HiRegisterOpcode::Compare,
+                        },
+                        rs,
+                        rd,
+                    })
+                }
+            },
+            ("bx", 1) | ("blx", 1) if parse_register(operands[0]).is_ok() => {
+                let link = mnemonic == "blx";
+                Thumb32::HiRegisterOperation(HiRegisterOperation {
+                    opcode: if link {
+                        HiRegisterOpcode::BranchLinkExchange
+                    } else {
+                        HiRegisterOpcode::BranchExchange
+                    },
+                    rs: parse_register(operands[0])?,
+                    rd: if link { 8 } else { 0 },
+                })
+            },
+            ("and", 2) | ("eor", 2) | ("lsl", 2) | ("lsr", 2) | ("asr", 2) | ("adc", 2) |
+            ("sbc", 2) | ("ror", 2) | ("tst", 2) | ("neg", 2) | ("cmn", 2) | ("orr", 2) |
+            ("mul", 2) | ("bic", 2) | ("mvn", 2) => {
+                let opcode = match mnemonic.as_str() {
+                    "and" => ThumbAluOpcode::And,
+                    "eor" => ThumbAluOpcode::ExclusiveOr,
+                    "lsl" => ThumbAluOpcode::LogicalLeft,
+                    "lsr" => ThumbAluOpcode::LogicalRight,
+                    "asr" => ThumbAluOpcode::ArithmeticRight,
+                    "adc" => ThumbAluOpcode::AddCarry,
+                    "sbc" => ThumbAluOpcode::SubtractCarry,
+                    "ror" => ThumbAluOpcode::RotateRight,
+                    "tst" => ThumbAluOpcode::Test,
+                    "neg" => ThumbAluOpcode::Negate,
+                    "cmn" => ThumbAluOpcode::CompareNegative,
+                    "orr" => ThumbAluOpcode::Or,
+                    "mul" => ThumbAluOpcode::Multiply,
+                    "bic" => ThumbAluOpcode::BitClear,
+                    _ => ThumbAluOpcode::MoveNot,
+                };
+                Thumb32::AluOperation(AluOperation {
+                    opcode,
+                    rs: parse_low_register(text, operands[1])?,
+                    rd: parse_low_register(text, operands[0])?,
+                })
+            },
+            ("ldr", 2) | ("str", 2) | ("ldrb", 2) | ("strb", 2) | ("ldrh", 2) | ("strh", 2) |
+            ("ldsb", 2) | ("ldsh", 2) | ("ldrsb", 2) | ("ldrsh", 2) =>
+                assemble_thumb_transfer(text, &mnemonic, &operands)?,
+            ("push", 1) | ("pop", 1) => {
+                let load = mnemonic == "pop";
+                let (mut register_list, _) = parse_register_list(operands[0])?;
+                let extra_register = if load { 1 << 15 } else { 1 << 14 };
+                let pc_or_lr = register_list & extra_register != 0;
+                register_list &= !extra_register;
+                if register_list > 0xFF {
+                    return Err(invalid());
+                }
+                Thumb32::PushPop(PushPop { load, pc_or_lr, register_list: register_list as u8 })
+            },
+            ("ldmia", 2) | ("stmia", 2) | ("ldm", 2) | ("stm", 2) => {
+                let rb = operands[0].strip_suffix('!').ok_or_else(invalid)?;
+                let (register_list, _) = parse_register_list(operands[1])?;
+                if register_list > 0xFF {
+                    return Err(invalid());
+                }
+                Thumb32::MultipleLoadStore(MultipleLoadStore {
+                    load: mnemonic.starts_with("ldm"),
+                    rb: parse_low_register(text, rb)?,
+                    register_list: register_list as u8,
+                })
+            },
+            ("swi", 1) => {
+                let comment = parse_immediate(operands[0])?;
+                if !(0..=0xFF).contains(&comment) {
+                    return Err(out_of_range());
+                }
+                Thumb32::SoftwareInterrupt(ThumbSoftwareInterrupt { comment: comment as u8 })
+            },
+            ("bl", 1) | ("blx", 1) => {
+                // Only the halves can be written, since each instruction is on its own
+                let operand: String = operands[0].chars().filter(|c| !c.is_whitespace()).collect();
+                if let Some(target) = operand.strip_prefix("lr=") {
+                    let offset = parse_number(target)? - pc;
+                    if mnemonic != "bl" || offset & 0xFFF != 0 ||
+                        !(-0x40_0000..0x40_0000).contains(&offset) {
+                        return Err(out_of_range());
+                    }
+                    Thumb32::LongBranchWithLink(LongBranchWithLink {
+                        low: false,
+                        exchange: false,
+                        offset: ((offset >> 12) & 0x7FF) as u16,
+                    })
+                } else if let Some(offset) = operand.strip_prefix("lr+") {
+                    let offset = parse_immediate(offset)?;
+                    if !(0..=0xFFE).contains(&offset) || offset & 1 != 0 {
+                        return Err(out_of_range());
+                    }
+                    Thumb32::LongBranchWithLink(LongBranchWithLink {
+                        low: true,
+                        exchange: mnemonic == "blx",
+                        offset: (offset >> 1) as u16,
+                    })
+                } else {
+                    return Err(invalid());
+                }
+            },
+            ("b", 1) => {
+                let offset = parse_number(operands[0])? - pc;
+                if !(-0x800..0x800).contains(&offset) || offset & 1 != 0 {
+                    return Err(out_of_range());
+                }
+                Thumb32::UnconditionalBranch(UnconditionalBranch { offset: offset as i16 })
+            },
+            (_, 1) if mnemonic.starts_with('b') => {
+                let condition = parse_condition(&mnemonic[1..])
+                    .filter(|condition| *condition != Condition::Always &&
+                        *condition != Condition::AllSet)
+                    .ok_or_else(|| AssemblyError::UnknownMnemonic(mnemonic.clone()))?;
+                let offset = parse_number(operands[0])? - pc;
+                if !(-0x100..0x100).contains(&offset) || offset & 1 != 0 {
+                    return Err(out_of_range());
+                }
+                Thumb32::ConditionalBranch(ConditionalBranch { condition, offset: offset as i16 })
+            },
+            _ => return Err(AssemblyError::UnknownMnemonic(mnemonic)),
+        };
+        Ok(instruction)
+    }
+
+    /// Reads a whole `bl <target>` (or `blx <target>`) and gives back both of its halves.
+    /// The address is where the 1st half will live.
+    pub fn assemble_pair(text: &str, address: u32) -> AssemblyResult<[Thumb32; 2]> {
+        let (mnemonic, operands) = split_line(text);
+        let exchange = match (mnemonic.as_str(), operands.len()) {
+            ("bl", 1) => false,
+            ("blx", 1) if parse_register(&operands[0]).is_err() => true,
+            _ => return Err(AssemblyError::InvalidOperands(text.to_string())),
+        };
+        let target = parse_number(&operands[0])?;
+        // BLX goes to ARM, so the target has to be word aligned
+        let alignment = if exchange { 0b11 } else { 0b1 };
+        // The PC is 2 instructions ahead of the 1st half
+        let offset = target - (address as i64 + 4);
+        if !(-0x40_0000..0x40_0000).contains(&offset) || target & alignment != 0 {
+            return Err(AssemblyError::OutOfRange(text.to_string()));
+        }
+        Ok([
+            Thumb32::LongBranchWithLink(LongBranchWithLink {
+                low: false,
+                exchange: false,
+                offset: ((offset >> 12) & 0x7FF) as u16,
+            }),
+            Thumb32::LongBranchWithLink(LongBranchWithLink {
+                low: true,
+                exchange,
+                offset: ((offset >> 1) & 0x7FF) as u16,
+            }),
+        ])
+    }
+}
+
+/// Where a transfer reads from or writes to
+struct AddressMode {
+    rn: u8,
+    pre_index: bool,
+    write_back: bool,
+    up: bool,
+    offset: AddressOffset,
+}
+enum AddressOffset {
+    Immediate(u32),
+    Register { rm: u8, shift: Shift },
+}
+
+const NO_SHIFT: Shift = Shift {
+    shift_type: ShiftType::LogicalLeft,
+    amount: ShiftAmount::Immediate(0),
+};
+
+fn assemble_data_processing(text: &str, form: &str, condition: Condition, operands: &[&str])
+-> AssemblyResult<Arm32> {
+    let opcode = match &form[..3] {
+        "and" => AluOpcode::And,
+        "eor" => AluOpcode::ExclusiveOr,
+        "sub" => AluOpcode::Subtract,
+        "rsb" => AluOpcode::ReverseSubtract,
+        "add" => AluOpcode::Add,
+        "adc" => AluOpcode::AddCarry,
+        "sbc" => AluOpcode::SubtractCarry,
+        "rsc" => AluOpcode::ReverseSubtractCarry,
+        "tst" => AluOpcode::Test,
+        "teq" => AluOpcode::TestEqual,
+        "cmp" => AluOpcode::Compare,
+        "cmn" => AluOpcode::CompareNegative,
+        "orr" => AluOpcode::Or,
+        "mov" => AluOpcode::Move,
+        "bic" => AluOpcode::BitClear,
+        "mvn" => AluOpcode::MoveNot,
+        _ => return Err(AssemblyError::UnknownMnemonic(form.to_string())),
+    };
+    // Tests only have Rn and moves only have Rd
+    let (rn, rd, operand2) = if opcode.is_test() {
+        (parse_register(operands.first().unwrap_or(&""))?, 0, operands.get(1..))
+    } else if opcode == AluOpcode::Move || opcode == AluOpcode::MoveNot {
+        (0, parse_register(operands.first().unwrap_or(&""))?, operands.get(1..))
+    } else {
+        (parse_register(operands.get(1).unwrap_or(&""))?,
+            parse_register(operands.first().unwrap_or(&""))?, operands.get(2..))
+    };
+
+    let operand2 = match operand2 {
+        Some([immediate]) if immediate.starts_with('#') => {
+            let (value, rotate) = encode_immediate(parse_immediate(immediate)? as u32)
+                .ok_or_else(|| AssemblyError::OutOfRange(text.to_string()))?;
+            ShifterOperand::Immediate { value, rotate }
+        },
+        Some([rm]) => ShifterOperand::Register { rm: parse_register(rm)?, shift: NO_SHIFT },
+        Some([rm, shift]) =>
+            ShifterOperand::Register { rm: parse_register(rm)?, shift: parse_shift(shift)? },
+        _ => return Err(AssemblyError::InvalidOperands(text.to_string())),
+    };
+    Ok(Arm32::DataProcessing(DataProcessing {
+        condition,
+        opcode,
+        set_condition_codes: opcode.is_test() || form.ends_with('s'),
+        rn,
+        rd,
+        operand2,
+    }))
+}
+
+fn assemble_halfword_multiply(text: &str, form: &str, condition: Condition, operands: &[&str])
+-> AssemblyResult<Arm32> {
+    let is_top = |half: u8| half == b't';
+    let halves = form.as_bytes();
+    // The word multiplies only have the y half
+    let (opcode, x_top, y_top, operand_count) = match &form[..5] {
+        "smlaw" => (HalfwordMultiplyOpcode::MultiplyAccumulateWord, false, is_top(halves[5]), 4),
+        "smulw" => (HalfwordMultiplyOpcode::MultiplyWord, false, is_top(halves[5]), 3),
+        "smlal" => (HalfwordMultiplyOpcode::MultiplyAccumulateLong, is_top(halves[5]),
+            is_top(halves[6]), 4),
+        _ if form.starts_with("smla") => (HalfwordMultiplyOpcode::MultiplyAccumulate,
+            is_top(halves[4]), is_top(halves[5]), 4),
+        _ => (HalfwordMultiplyOpcode::Multiply, is_top(halves[4]), is_top(halves[5]), 3),
+    };
+    expect_count(text, operands, operand_count)?;
+
+    let registers = operands.iter()
+        .map(|operand| parse_register(operand))
+        .collect::<AssemblyResult<Vec<u8>>>()?;
+    // RdLo is in Rn and RdHi is in Rd
+    let (rd, rn, rm, rs) = match opcode {
+        HalfwordMultiplyOpcode::MultiplyAccumulateLong =>
+            (registers[1], registers[0], registers[2], registers[3]),
+        _ => (registers[0], *registers.get(3).unwrap_or(&0), registers[1], registers[2]),
+    };
+    Ok(Arm32::SignedHalfwordMultiply(SignedHalfwordMultiply {
+        condition,
+        opcode,
+        rd,
+        rn,
+        rs,
+        rm,
+        x_top,
+        y_top,
+    }))
+}
+
+fn assemble_thumb_transfer(text: &str, mnemonic: &str, operands: &[&str])
+-> AssemblyResult<Thumb32> {
+    let invalid = || AssemblyError::InvalidOperands(text.to_string());
+    let out_of_range = || AssemblyError::OutOfRange(text.to_string());
+    let rd = parse_low_register(text, operands[0])?;
+    let inside = operands[1].strip_prefix('[').and_then(|inside| inside.strip_suffix(']'))
+        .ok_or_else(invalid)?;
+    let inside: Vec<&str> = inside.split(',').map(|part| part.trim()).collect();
+    let load = mnemonic.starts_with("ld");
+
+    // Register offsets first, since they don't need any scaling
+    if inside.len() == 2 && !inside[1].starts_with('#') {
+        let rb = parse_low_register(text, inside[0])?;
+        let ro = parse_low_register(text, inside[1])?;
+        let instruction = match mnemonic {
+            "ldr" | "str" | "ldrb" | "strb" => Thumb32::LoadStoreRegisterOffset(
+                LoadStoreRegisterOffset { load, byte: mnemonic.ends_with('b'), ro, rb, rd }),
+            _ => {
+                let transfer_type = match mnemonic {
+                    "strh" => SignExtendedType::StoreHalfword,
+                    "ldsb" | "ldrsb" => SignExtendedType::LoadSignedByte,
+                    "ldrh" => SignExtendedType::LoadHalfword,
+                    _ => SignExtendedType::LoadSignedHalfword,
+                };
+                Thumb32::LoadStoreSignExtended(LoadStoreSignExtended { transfer_type, ro, rb, rd })
+            },
+        };
+        return Ok(instruction);
+    }
+
+    let offset = match inside.get(1) {
+        Some(offset) => parse_immediate(offset)?,
+        None => 0,
+    };
+    let fits = |max: i64, scale: i64| offset >= 0 && offset <= max && offset % scale == 0;
+    let instruction = match (mnemonic, inside[0]) {
+        ("ldr", "pc") if fits(0x3FC, 4) =>
+            Thumb32::PcRelativeLoad(PcRelativeLoad { rd, offset: offset as u16 }),
+        ("ldr", "sp") | ("str", "sp") if fits(0x3FC, 4) =>
+            Thumb32::SpRelativeLoadStore(SpRelativeLoadStore { load, rd, offset: offset as u16 }),
+        ("ldr", rb) | ("str", rb) if fits(0x7C, 4) =>
+            Thumb32::LoadStoreImmediateOffset(LoadStoreImmediateOffset {
+                load,
+                byte: false,
+                offset: offset as u8,
+                rb: parse_low_register(text, rb)?,
+                rd,
+            }),
+        ("ldrb", rb) | ("strb", rb) if fits(0x1F, 1) =>
+            Thumb32::LoadStoreImmediateOffset(LoadStoreImmediateOffset {
+                load,
+                byte: true,
+                offset: offset as u8,
+                rb: parse_low_register(text, rb)?,
+                rd,
+            }),
+        ("ldrh", rb) | ("strh", rb) if fits(0x3E, 2) =>
+            Thumb32::LoadStoreHalfword(LoadStoreHalfword {
+                load,
+                offset: offset as u8,
+                rb: parse_low_register(text, rb)?,
+                rd,
+            }),
+        ("ldr", _) | ("str", _) | ("ldrb", _) | ("strb", _) | ("ldrh", _) | ("strh", _) =>
+            return Err(out_of_range()),
+        _ => return Err(invalid()),
+    };
+    Ok(instruction)
+}
+
+/// Reads the Rd and address operands of an ARM load or store
+fn parse_transfer(text: &str, operands: &[&str]) -> AssemblyResult<(u8, AddressMode)> {
+    let invalid = || AssemblyError::InvalidOperands(text.to_string());
+    if operands.len() < 2 {
+        return Err(invalid());
+    }
+    let rd = parse_register(operands[0])?;
+
+    let (inside, pre_index, write_back) = if let Some(inside) = operands[1].strip_suffix("]!") {
+        (inside, true, true)
+    } else if let Some(inside) = operands[1].strip_suffix(']') {
+        (inside, operands.len() == 2, false)
+    } else {
+        return Err(invalid());
+    };
+    let inside = inside.strip_prefix('[').ok_or_else(invalid)?;
+    let mut parts: Vec<&str> = inside.split(',').map(|part| part.trim()).collect();
+    let rn = parse_register(parts.remove(0))?;
+    // Post-indexed offsets come after the brackets instead
+    let offset_parts = if pre_index { parts } else { operands[2..].to_vec() };
+
+    let (up, offset) = match offset_parts.as_slice() {
+        [] => (true, AddressOffset::Immediate(0)),
+        [immediate] if immediate.starts_with('#') => {
+            let offset = parse_immediate(immediate)?;
+            let up = offset >= 0 && !immediate.starts_with("#-");
+            (up, AddressOffset::Immediate(offset.unsigned_abs() as u32))
+        },
+        [rm, shift @ ..] => {
+            let (up, rm) = match rm.strip_prefix('-') {
+                Some(rm) => (false, rm),
+                None => (true, rm.strip_prefix('+').unwrap_or(rm)),
+            };
+            let shift = match shift {
+                [] => NO_SHIFT,
+                [shift] => parse_shift(shift)?,
+                _ => return Err(invalid()),
+            };
+            (up, AddressOffset::Register { rm: parse_register(rm)?, shift })
+        },
+    };
+    Ok((rd, AddressMode { rn, pre_index, write_back, up, offset }))
+}
+
+/// Splits the line into the lowercase mnemonic and its operands (ignoring any comment)
+fn split_line(text: &str) -> (String, Vec<String>) {
+    let line = text.split(';').next().unwrap_or("").trim().to_lowercase();
+    let (mnemonic, operands) = match line.find(char::is_whitespace) {
+        Some(index) => (line[..index].to_string(), line[index..].trim()),
+        None => (line.clone(), ""),
+    };
+
+    // Commas inside of brackets and braces belong to that operand
+    let mut split_operands = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in operands.chars() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                split_operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            _ => (),
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        split_operands.push(current.trim().to_string());
+    }
+    (mnemonic, split_operands)
+}
+
+fn expect_count(text: &str, operands: &[&str], count: usize) -> AssemblyResult<()> {
+    if operands.len() == count {
+        Ok(())
+    } else {
+        Err(AssemblyError::InvalidOperands(text.to_string()))
+    }
+}
+
+fn parse_condition(suffix: &str) -> Option<Condition> {
+    let condition = match suffix {
+        "eq" => Condition::Equal,
+        "ne" => Condition::NEqual,
+        "cs" | "hs" => Condition::CarrySet,
+        "cc" | "lo" => Condition::CarryClear,
+        "mi" => Condition::Minus,
+        "pl" => Condition::Positive,
+        "vs" => Condition::VSet,
+        "vc" => Condition::VClear,
+        "hi" => Condition::UHigh,
+        "ls" => Condition::ULow,
+        "ge" => Condition::GreaterEqual,
+        "lt" => Condition::Less,
+        "gt" => Condition::Greater,
+        "le" => Condition::LessEqual,
+        "" | "al" => Condition::Always,
+        "nv" => Condition::AllSet,
+        _ => return None,
+    };
+    Some(condition)
+}
+
+fn parse_register(operand: &str) -> AssemblyResult<u8> {
+    let register = match operand {
+        "sp" => Some(13),
+        "lr" => Some(14),
+        "pc" => Some(15),
+        _ => operand.strip_prefix('r')
+            .and_then(|number| number.parse::<u8>().ok())
+            .filter(|register| *register < 16),
+    };
+    register.ok_or_else(|| AssemblyError::InvalidOperands(operand.to_string()))
+}
+
+/// Thumb mostly only has 3 bits for its registers
+fn parse_low_register(text: &str, operand: &str) -> AssemblyResult<u8> {
+    match parse_register(operand)? {
+        register if register < 8 => Ok(register),
+        _ => Err(AssemblyError::InvalidOperands(text.to_string())),
+    }
+}
+
+/// Reads a list like {r0-r3, lr}, with a trailing ^ for the PSR or user bank
+fn parse_register_list(operand: &str) -> AssemblyResult<(u16, bool)> {
+    let invalid = || AssemblyError::InvalidOperands(operand.to_string());
+    let (list, psr_or_user) = match operand.strip_suffix('^') {
+        Some(list) => (list.trim(), true),
+        None => (operand, false),
+    };
+    let list = list.strip_prefix('{').and_then(|list| list.strip_suffix('}'))
+        .ok_or_else(invalid)?;
+
+    let mut register_list = 0;
+    for part in list.split(',').map(|part| part.trim()).filter(|part| !part.is_empty()) {
+        let (start, end) = match part.find('-') {
+            Some(index) =>
+                (parse_register(part[..index].trim())?, parse_register(part[index + 1..].trim())?),
+            None => (parse_register(part)?, parse_register(part)?),
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for register in start..=end {
+            register_list |= 1 << register;
+        }
+    }
+    Ok((register_list, psr_or_user))
+}
+
+/// Reads a shift like "lsl #2", "asr r3" or "rrx"
+fn parse_shift(operand: &str) -> AssemblyResult<Shift> {
+    let invalid = || AssemblyError::InvalidOperands(operand.to_string());
+    if operand == "rrx" {
+        return Ok(Shift {
+            shift_type: ShiftType::RotateRightExtended,
+            amount: ShiftAmount::Immediate(1),
+        });
+    }
+
+    let mut parts = operand.split_whitespace();
+    let shift_type = match parts.next() {
+        Some("lsl") | Some("asl") => ShiftType::LogicalLeft,
+        Some("lsr") => ShiftType::LogicalRight,
+        Some("asr") => ShiftType::ArithmeticRight,
+        Some("ror") => ShiftType::RotateRight,
+        _ => return Err(invalid()),
+    };
+    let amount = parts.next().ok_or_else(invalid)?;
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+
+    let amount = if amount.starts_with('#') {
+        let amount = parse_immediate(amount)?;
+        // LSR and ASR can go up to 32, but ROR #0 is RRX instead
+        let range = match shift_type {
+            ShiftType::LogicalLeft => 0..=31,
+            ShiftType::LogicalRight | ShiftType::ArithmeticRight => 1..=32,
+            _ => 1..=31,
+        };
+        if !range.contains(&amount) {
+            return Err(AssemblyError::OutOfRange(operand.to_string()));
+        }
+        ShiftAmount::Immediate(amount as u8)
+    } else {
+        ShiftAmount::Register(parse_register(amount)?)
+    };
+    Ok(Shift { shift_type, amount })
+}
+
+/// Reads an immediate like #12, #-4 or #0x1F
+fn parse_immediate(operand: &str) -> AssemblyResult<i64> {
+    operand.strip_prefix('#')
+        .ok_or_else(|| AssemblyError::InvalidOperands(operand.to_string()))
+        .and_then(parse_number)
+}
+
+fn parse_number(operand: &str) -> AssemblyResult<i64> {
+    let (negative, number) = match operand.trim().strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, operand.trim()),
+    };
+    let value = match number.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => number.parse::<i64>(),
+    };
+    let value = value.map_err(|_| AssemblyError::InvalidOperands(operand.to_string()))?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Finds the 8 bit value and the (even) rotate right that make up the immediate
+fn encode_immediate(immediate: u32) -> Option<(u8, u8)> {
+    (0..16)
+        .map(|half_rotate| half_rotate * 2)
+        .find(|rotate| immediate.rotate_left(*rotate) <= 0xFF)
+        .map(|rotate| (immediate.rotate_left(rotate) as u8, rotate as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ArmArchitecture, test_words::random_words};
+    use super::*;
+
+    const ADDRESS: u32 = 0x0800_0000;
+
+    #[test]
+    fn thumb_assemble_reads_back_every_disassembly() {
+        for architecture in [ArmArchitecture::V4T, ArmArchitecture::V5TE] {
+            for instruction in 0..=u16::MAX {
+                if let Some(decoded) = Thumb32::find_instruction(instruction, architecture) {
+                    let text = decoded.disassemble(ADDRESS);
+                    let assembled = Thumb32::assemble(&text, ADDRESS)
+                        .unwrap_or_else(|e| panic!("{:04X} {}: {:?}", instruction, text, e));
+                    assert_eq!(assembled.disassemble(ADDRESS), text, "{:04X}", instruction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arm_assemble_reads_back_sampled_disassembly() {
+        for instruction in random_words(300_000) {
+            for architecture in [ArmArchitecture::V4T, ArmArchitecture::V5TE] {
+                if let Some(decoded) = Arm32::find_instruction(instruction, architecture) {
+                    let text = decoded.disassemble(ADDRESS);
+                    let assembled = Arm32::assemble(&text, ADDRESS)
+                        .unwrap_or_else(|e| panic!("{:08X} {}: {:?}", instruction, text, e));
+                    assert_eq!(assembled.disassemble(ADDRESS), text, "{:08X}", instruction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn thumb_pair_reads_back_whole_branches() {
+        for text in ["bl 0x08001000", "bl 0x07C00004", "bl 0x083FFFFE", "blx 0x08000100"] {
+            let [high, low] = Thumb32::assemble_pair(text, ADDRESS).unwrap();
+            assert_eq!(high.disassemble_pair(Some(&low), ADDRESS), text);
+        }
+        assert!(Thumb32::assemble_pair("bl 0x08400004", ADDRESS).is_err());
+        assert!(Thumb32::assemble_pair("blx 0x08000102", ADDRESS).is_err());
+        assert!(Thumb32::assemble_pair("blx r0", ADDRESS).is_err());
+    }
+
+    #[test]
+    fn bad_input_gives_an_error() {
+        let arm_lines = [
+            "", "b", "bl", "b r0", "bl r1", "blx", "bx", "mov", "mov r0", "add r0, r1",
+            "ldr r0", "ldr r0, r1", "ldr r0, [r1", "ldm r0", "ldmia r0, r1", "push r0",
+            "mul r0, r1", "msr cpsr_q, r0", "mrs r0, r1", "swp r0, r1, r2", "swi #0x1000000",
+            "mov r0, #0x101", "mov r0, r1, lsl #32", "str r0, [r1, #4096]", "clz r0",
+            "smlabb r0, r1", "smlal r0, r1", "r0, r1", "movxx r0, r1", "mov r16, r0",
+            "ldrh r0, [r1, r2, lsl #1]", "b 0x0c000000", "b 0x08000001",
+        ];
+        for text in arm_lines {
+            assert!(Arm32::assemble(text, ADDRESS).is_err(), "{}", text);
+        }
+
+        let thumb_lines = [
+            "", "b", "bl", "bl r0", "bl lr = 0x08001001", "bl 0x08001000", "lsl r0, r1, #32",
+            "add r8, r1, r2", "add sp, #1", "mov r0, #256", "ldr r0, [r8]", "ldr r0, [r1, #3]",
+            "push {r8}", "ldmia r0, {r1}", "swi #256", "beq 0x08001000", "bal 0x08000000",
+            "b 0x08000801", "mul r0", "ldr r0", "ldr r0, r1",
+        ];
+        for text in thumb_lines {
+            assert!(Thumb32::assemble(text, ADDRESS).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use super::{
+    arm32::*,
+    thumb32::*,
+};
+
+impl Arm32 {
+    /// Turns the instruction into assembly text (lowercase, with the condition after the mnemonic).
+    /// The address is where this instruction lives, so that branch targets can be resolved.
+    pub fn disassemble(&self, address: u32) -> String {
+        match self {
+            Arm32::Branch(branch) => {
+                // The PC is 2 instructions ahead
+                let mut target = address.wrapping_add(8).wrapping_add(branch.offset as u32);
+                if branch.condition == Condition::AllSet {
+                    if branch.opcode {
+                        target = target.wrapping_add(2);
+                    }
+                    format!("blx {}", format_address(target))
+                } else {
+                    let mnemonic = if branch.opcode { "bl" } else { "b" };
+                    format!("{}{} {}", mnemonic, condition_suffix(branch.condition),
+                        format_address(target))
+                }
+            },
+            Arm32::BranchExchange(branch) => {
+                let mnemonic = if branch.link { "blx" } else { "bx" };
+                format!("{}{} {}", mnemonic, condition_suffix(branch.condition),
+                    register_name(branch.rn))
+            },
+            Arm32::DataProcessing(instruction) => disassemble_data_processing(instruction),
+            Arm32::SingleDataTransfer(transfer) => {
+                let mnemonic = if transfer.load { "ldr" } else { "str" };
+                let byte = if transfer.byte { "b" } else { "" };
+                // Post-indexing with write back is the forced user mode access
+                let user = if !transfer.pre_index && transfer.write_back { "t" } else { "" };
+                let offset = match transfer.offset {
+                    TransferOffset::Immediate(0) => None,
+                    TransferOffset::Immediate(offset) => Some(format!("#{}{}",
+                        sign(transfer.up), format_immediate(offset as u32))),
+                    TransferOffset::Register { rm, shift } => Some(format!("{}{}{}",
+                        sign(transfer.up), register_name(rm), format_shift(shift))),
+                };
+                format!("{}{}{}{} {}, {}", mnemonic, byte, user,
+                    condition_suffix(transfer.condition), register_name(transfer.rd),
+                    format_address_mode(transfer.rn, offset, transfer.pre_index,
+                        transfer.write_back))
+            },
+            Arm32::HalfwordDataTransfer(transfer) => {
+                let mnemonic = match (transfer.load, transfer.transfer_type) {
+                    (false, _) => "strh",
+                    (true, HalfwordTransferType::Halfword) => "ldrh",
+                    (true, HalfwordTransferType::SignedByte) => "ldrsb",
+                    (true, HalfwordTransferType::SignedHalfword) => "ldrsh",
+                };
+                let offset = match transfer.offset {
+                    HalfwordOffset::Immediate(0) => None,
+                    HalfwordOffset::Immediate(offset) => Some(format!("#{}{}",
+                        sign(transfer.up), format_immediate(offset as u32))),
+                    HalfwordOffset::Register(rm) =>
+                        Some(format!("{}{}", sign(transfer.up), register_name(rm))),
+                };
+                format!("{}{} {}, {}", mnemonic, condition_suffix(transfer.condition),
+                    register_name(transfer.rd),
+                    format_address_mode(transfer.rn, offset, transfer.pre_index,
+                        transfer.write_back))
+            },
+            Arm32::BlockDataTransfer(transfer) => {
+                let suffix = condition_suffix(transfer.condition);
+                let user = if transfer.psr_or_user { "^" } else { "" };
+                let register_list = format_register_list(transfer.register_list);
+                let is_push = !transfer.load && transfer.pre_index && !transfer.up;
+                let is_pop = transfer.load && !transfer.pre_index && transfer.up;
+                if transfer.rn == 13 && transfer.write_back && (is_push || is_pop) {
+                    let mnemonic = if transfer.load { "pop" } else { "push" };
+                    return format!("{}{} {}{}", mnemonic, suffix, register_list, user);
+                }
+
+                let mnemonic = if transfer.load { "ldm" } else { "stm" };
+                let mode = match (transfer.pre_index, transfer.up) {
+                    (false, true) => "ia",
+                    (true, true) => "ib",
+                    (false, false) => "da",
+                    (true, false) => "db",
+                };
+                let write_back = if transfer.write_back { "!" } else { "" };
+                format!("{}{}{} {}{}, {}{}", mnemonic, mode, suffix,
+                    register_name(transfer.rn), write_back, register_list, user)
+            },
+            Arm32::Multiply(multiply) => {
+                let set = if multiply.set_condition_codes { "s" } else { "" };
+                let suffix = condition_suffix(multiply.condition);
+                if multiply.accumulate {
+                    format!("mla{}{} {}, {}, {}, {}", set, suffix, register_name(multiply.rd),
+                        register_name(multiply.rm), register_name(multiply.rs),
+                        register_name(multiply.rn))
+                } else {
+                    format!("mul{}{} {}, {}, {}", set, suffix, register_name(multiply.rd),
+                        register_name(multiply.rm), register_name(multiply.rs))
+                }
+            },
+            Arm32::MultiplyLong(multiply) => {
+                let sign = if multiply.signed { "s" } else { "u" };
+                let operation = if multiply.accumulate { "mlal" } else { "mull" };
+                let set = if multiply.set_condition_codes { "s" } else { "" };
+                format!("{}{}{}{} {}, {}, {}, {}", sign, operation, set,
+                    condition_suffix(multiply.condition), register_name(multiply.rd_lo),
+                    register_name(multiply.rd_hi), register_name(multiply.rm),
+                    register_name(multiply.rs))
+            },
+            Arm32::StatusToRegister(transfer) => {
+                format!("mrs{} {}, {}", condition_suffix(transfer.condition),
+                    register_name(transfer.rd), if transfer.spsr { "spsr" } else { "cpsr" })
+            },
+            Arm32::RegisterToStatus(transfer) => {
+                let status_register = if transfer.spsr { "spsr" } else { "cpsr" };
+                let fields: String = [(3, 'f'), (2, 's'), (1, 'x'), (0, 'c')].iter()
+                    .filter(|(bit, _)| transfer.field_mask & (1 << bit) != 0)
+                    .map(|(_, field)| field)
+                    .collect();
+                let operand = match transfer.operand {
+                    StatusOperand::Immediate { value, rotate } =>
+                        format!("#{}", format_immediate((value as u32).rotate_right(rotate as u32))),
+                    StatusOperand::Register(rm) => register_name(rm).to_string(),
+                };
+                format!("msr{} {}_{}, {}", condition_suffix(transfer.condition),
+                    status_register, fields, operand)
+            },
+            Arm32::Swap(swap) => {
+                format!("swp{}{} {}, {}, [{}]", if swap.byte { "b" } else { "" },
+                    condition_suffix(swap.condition), register_name(swap.rd),
+                    register_name(swap.rm), register_name(swap.rn))
+            },
+            Arm32::SoftwareInterrupt(interrupt) => {
+                format!("swi{} #{}", condition_suffix(interrupt.condition),
+                    format_immediate(interrupt.comment))
+            },
+            Arm32::CountLeadingZeros(count) => {
+                format!("clz{} {}, {}", condition_suffix(count.condition),
+                    register_name(count.rd), register_name(count.rm))
+            },
+            Arm32::SaturatingArithmetic(arithmetic) => {
+                let mnemonic = match arithmetic.opcode {
+                    SaturatingOpcode::Add => "qadd",
+                    SaturatingOpcode::Subtract => "qsub",
+                    SaturatingOpcode::DoubleAdd => "qdadd",
+                    SaturatingOpcode::DoubleSubtract => "qdsub",
+                };
+                format!("{}{} {}, {}, {}", mnemonic, condition_suffix(arithmetic.condition),
+                    register_name(arithmetic.rd), register_name(arithmetic.rm),
+                    register_name(arithmetic.rn))
+            },
+            Arm32::SignedHalfwordMultiply(multiply) => disassemble_halfword_multiply(multiply),
+        }
+    }
+}
+
+impl Thumb32 {
+    /// Turns the instruction into assembly text (lowercase).
+    /// The address is where this instruction lives, so that branch targets can be resolved.
+    pub fn disassemble(&self, address: u32) -> String {
+        // The PC is 2 instructions ahead
+        let pc = address.wrapping_add(4);
+        match self {
+            Thumb32::MoveShiftedRegister(shift) => {
+                let mnemonic = shift_mnemonic(shift.shift.shift_type);
+                let amount = match shift.shift.amount {
+                    ShiftAmount::Immediate(amount) => amount,
+                    ShiftAmount::Register(_) => panic!("Programming error"),
+                };
+                format!("{} {}, {}, #{}", mnemonic, register_name(shift.rd),
+                    register_name(shift.rs), amount)
+            },
+            Thumb32::AddSubtract(add) => {
+                let mnemonic = if add.subtract { "sub" } else { "add" };
+                let operand = if add.immediate {
+                    format!("#{}", add.operand)
+                } else {
+                    register_name(add.operand).to_string()
+                };
+                format!("{} {}, {}, {}", mnemonic, register_name(add.rd), register_name(add.rs),
+                    operand)
+            },
+            Thumb32::ImmediateOperation(operation) => {
+                let mnemonic = match operation.opcode {
+                    ImmediateOpcode::Move => "mov",
+                    ImmediateOpcode::Compare => "cmp",
+                    ImmediateOpcode::Add => "add",
+                    ImmediateOpcode::Subtract => "sub",
+                };
+                format!("{} {}, #{}", mnemonic, register_name(operation.rd),
+                    format_immediate(operation.offset as u32))
+            },
+            Thumb32::AluOperation(operation) => {
+                let mnemonic = match operation.opcode {
+                    ThumbAluOpcode::And => "and",
+                    ThumbAluOpcode::ExclusiveOr => "eor",
+                    ThumbAluOpcode::LogicalLeft => "lsl",
+                    ThumbAluOpcode::LogicalRight => "lsr",
+                    ThumbAluOpcode::ArithmeticRight => "asr",
+                    ThumbAluOpcode::AddCarry => "adc",
+                    ThumbAluOpcode::SubtractCarry => "sbc",
+                    ThumbAluOpcode::RotateRight => "ror",
+                    ThumbAluOpcode::Test => "tst",
+                    ThumbAluOpcode::Negate => "neg",
+                    ThumbAluOpcode::Compare => "cmp",
+                    ThumbAluOpcode::CompareNegative => "cmn",
+                    ThumbAluOpcode::Or => "orr",
+                    ThumbAluOpcode::Multiply => "mul",
+                    ThumbAluOpcode::BitClear => "bic",
+                    ThumbAluOpcode::MoveNot => "mvn",
+                };
+                format!("{} {}, {}", mnemonic, register_name(operation.rd),
+                    register_name(operation.rs))
+            },
+            Thumb32::HiRegisterOperation(operation) => {
+                let mnemonic = match operation.opcode {
+                    HiRegisterOpcode::Add => "add",
+                    HiRegisterOpcode::Compare => "cmp",
+                    HiRegisterOpcode::Move => "mov",
+                    HiRegisterOpcode::BranchExchange =>
+                        return format!("bx {}", register_name(operation.rs)),
+                    HiRegisterOpcode::BranchLinkExchange =>
+                        return format!("blx {}", register_name(operation.rs)),
+                };
+                format!("{} {}, {}", mnemonic, register_name(operation.rd),
+                    register_name(operation.rs))
+            },
+            Thumb32::PcRelativeLoad(load) => {
+                let target = (pc & !0b11).wrapping_add(load.offset as u32);
+                format!("ldr {}, [pc, #{}] ; {}", register_name(load.rd),
+                    format_immediate(load.offset as u32), format_address(target))
+            },
+            Thumb32::LoadStoreRegisterOffset(transfer) => {
+                let mnemonic = if transfer.load { "ldr" } else { "str" };
+                format!("{}{} {}, [{}, {}]", mnemonic, if transfer.byte { "b" } else { "" },
+                    register_name(transfer.rd), register_name(transfer.rb),
+                    register_name(transfer.ro))
+            },
+            Thumb32::LoadStoreSignExtended(transfer) => {
+                let mnemonic = match transfer.transfer_type {
+                    SignExtendedType::StoreHalfword => "strh",
+                    SignExtendedType::LoadSignedByte => "ldsb",
+                    SignExtendedType::LoadHalfword => "ldrh",
+                    SignExtendedType::LoadSignedHalfword => "ldsh",
+                };
+                format!("{} {}, [{}, {}]", mnemonic, register_name(transfer.rd),
+                    register_name(transfer.rb), register_name(transfer.ro))
+            },
+            Thumb32::LoadStoreImmediateOffset(transfer) => {
+                let mnemonic = if transfer.load { "ldr" } else { "str" };
+                format!("{}{} {}, [{}, #{}]", mnemonic, if transfer.byte { "b" } else { "" },
+                    register_name(transfer.rd), register_name(transfer.rb),
+                    format_immediate(transfer.offset as u32))
+            },
+            Thumb32::LoadStoreHalfword(transfer) => {
+                let mnemonic = if transfer.load { "ldrh" } else { "strh" };
+                format!("{} {}, [{}, #{}]", mnemonic, register_name(transfer.rd),
+                    register_name(transfer.rb), format_immediate(transfer.offset as u32))
+            },
+            Thumb32::SpRelativeLoadStore(transfer) => {
+                let mnemonic = if transfer.load { "ldr" } else { "str" };
+                format!("{} {}, [sp, #{}]", mnemonic, register_name(transfer.rd),
+                    format_immediate(transfer.offset as u32))
+            },
+            Thumb32::LoadAddress(load) => {
+                if load.sp {
+                    format!("add {}, sp, #{}", register_name(load.rd),
+                        format_immediate(load.offset as u32))
+                } else {
+                    let target = (pc & !0b11).wrapping_add(load.offset as u32);
+                    format!("add {}, pc, #{} ; {}", register_name(load.rd),
+                        format_immediate(load.offset as u32), format_address(target))
+                }
+            },
+            Thumb32::AddOffsetToSp(add) => {
+                let sign = if add.offset < 0 { "-" } else { "" };
+                format!("add sp, #{}{}", sign, format_immediate(add.offset.unsigned_abs() as u32))
+            },
+            Thumb32::PushPop(transfer) => {
+                let mut register_list = transfer.register_list as u16;
+                if transfer.pc_or_lr {
+                    register_list |= if transfer.load { 1 << 15 } else { 1 << 14 };
+                }
+                format!("{} {}", if transfer.load { "pop" } else { "push" },
+                    format_register_list(register_list))
+            },
+            Thumb32::MultipleLoadStore(transfer) => {
+                format!("{} {}!, {}", if transfer.load { "ldmia" } else { "stmia" },
+                    register_name(transfer.rb),
+                    format_register_list(transfer.register_list as u16))
+            },
+            Thumb32::ConditionalBranch(branch) => {
+                let target = pc.wrapping_add(branch.offset as i32 as u32);
+                format!("b{} {}", condition_suffix(branch.condition), format_address(target))
+            },
+            Thumb32::SoftwareInterrupt(interrupt) =>
+                format!("swi #{}", format_immediate(interrupt.comment as u32)),
+            Thumb32::UnconditionalBranch(branch) => {
+                let target = pc.wrapping_add(branch.offset as i32 as u32);
+                format!("b {}", format_address(target))
+            },
+            Thumb32::LongBranchWithLink(branch) => {
+                if branch.low {
+                    // The full target needs the upper half from the previous instruction
+                    let mnemonic = if branch.exchange { "blx" } else { "bl" };
+                    format!("{} lr + #{}", mnemonic, format_immediate((branch.offset as u32) << 1))
+                } else {
+                    format!("bl lr = {}", format_address(high_branch_target(branch, pc)))
+                }
+            },
+        }
+    }
+
+    /// Turns the instruction into assembly text, like `disassemble`, but with the instruction
+    /// after it (if there is one). The 1st half of a BL followed by its 2nd half shows the whole
+    /// call with the resolved target, in which case the 2nd half doesn't need to be shown.
+    pub fn disassemble_pair(&self, next: Option<&Thumb32>, address: u32) -> String {
+        match (self, next) {
+            (Thumb32::LongBranchWithLink(high), Some(Thumb32::LongBranchWithLink(low)))
+            if !high.low && low.low => {
+                // The PC is 2 instructions ahead of the 1st half
+                let target = high_branch_target(high, address.wrapping_add(4))
+                    .wrapping_add((low.offset as u32) << 1);
+                if low.exchange {
+                    // BLX goes to ARM, so the target is word aligned
+                    format!("blx {}", format_address(target & !0b11))
+                } else {
+                    format!("bl {}", format_address(target))
+                }
+            },
+            _ => self.disassemble(address),
+        }
+    }
+}
+
+/// What the 1st half of a BL puts in LR, which is the PC plus the sign extended upper offset
+fn high_branch_target(branch: &LongBranchWithLink, pc: u32) -> u32 {
+    let offset = (((branch.offset as u32) << 21) as i32 >> 9) as u32;
+    pc.wrapping_add(offset)
+}
+
+fn disassemble_data_processing(instruction: &DataProcessing) -> String {
+    let mnemonic = match instruction.opcode {
+        AluOpcode::And => "and",
+        AluOpcode::ExclusiveOr => "eor",
+        AluOpcode::Subtract => "sub",
+        AluOpcode::ReverseSubtract => "rsb",
+        AluOpcode::Add => "add",
+        AluOpcode::AddCarry => "adc",
+        AluOpcode::SubtractCarry => "sbc",
+        AluOpcode::ReverseSubtractCarry => "rsc",
+        AluOpcode::Test => "tst",
+        AluOpcode::TestEqual => "teq",
+        AluOpcode::Compare => "cmp",
+        AluOpcode::CompareNegative => "cmn",
+        AluOpcode::Or => "orr",
+        AluOpcode::Move => "mov",
+        AluOpcode::BitClear => "bic",
+        AluOpcode::MoveNot => "mvn",
+    };
+    let suffix = condition_suffix(instruction.condition);
+    let operand2 = match instruction.operand2 {
+        ShifterOperand::Immediate { value, rotate } =>
+            format!("#{}", format_immediate((value as u32).rotate_right(rotate as u32))),
+        ShifterOperand::Register { rm, shift } =>
+            format!("{}{}", register_name(rm), format_shift(shift)),
+    };
+
+    if instruction.opcode.is_test() {
+        // The S is implied for the tests
+        format!("{}{} {}, {}", mnemonic, suffix, register_name(instruction.rn), operand2)
+    } else {
+        let set = if instruction.set_condition_codes { "s" } else { "" };
+        match instruction.opcode {
+            AluOpcode::Move | AluOpcode::MoveNot => format!("{}{}{} {}, {}", mnemonic, set,
+                suffix, register_name(instruction.rd), operand2),
+            _ => format!("{}{}{} {}, {}, {}", mnemonic, set, suffix,
+                register_name(instruction.rd), register_name(instruction.rn), operand2),
+        }
+    }
+}
+
+fn disassemble_halfword_multiply(multiply: &SignedHalfwordMultiply) -> String {
+    let x = if multiply.x_top { "t" } else { "b" };
+    let y = if multiply.y_top { "t" } else { "b" };
+    let suffix = condition_suffix(multiply.condition);
+    let (rd, rm, rs, rn) = (register_name(multiply.rd), register_name(multiply.rm),
+        register_name(multiply.rs), register_name(multiply.rn));
+    match multiply.opcode {
+        HalfwordMultiplyOpcode::MultiplyAccumulate =>
+            format!("smla{}{}{} {}, {}, {}, {}", x, y, suffix, rd, rm, rs, rn),
+        HalfwordMultiplyOpcode::MultiplyAccumulateWord =>
+            format!("smlaw{}{} {}, {}, {}, {}", y, suffix, rd, rm, rs, rn),
+        HalfwordMultiplyOpcode::MultiplyWord =>
+            format!("smulw{}{} {}, {}, {}", y, suffix, rd, rm, rs),
+        // RdLo is in Rn and RdHi is in Rd
+        HalfwordMultiplyOpcode::MultiplyAccumulateLong =>
+            format!("smlal{}{}{} {}, {}, {}, {}", x, y, suffix, rn, rd, rm, rs),
+        HalfwordMultiplyOpcode::Multiply =>
+            format!("smul{}{}{} {}, {}, {}", x, y, suffix, rd, rm, rs),
+    }
+}
+
+fn condition_suffix(condition: Condition) -> &'static str {
+    match condition {
+        Condition::Equal => "eq",
+        Condition::NEqual => "ne",
+        Condition::CarrySet => "cs",
+        Condition::CarryClear => "cc",
+        Condition::Minus => "mi",
+        Condition::Positive => "pl",
+        Condition::VSet => "vs",
+        Condition::VClear => "vc",
+        Condition::UHigh => "hi",
+        Condition::ULow => "ls",
+        Condition::GreaterEqual => "ge",
+        Condition::Less => "lt",
+        Condition::Greater => "gt",
+        Condition::LessEqual => "le",
+        Condition::Always => "",
+        Condition::AllSet => "nv",
+    }
+}
+
+fn register_name(register: u8) -> &'static str {
+    const NAMES: [&str; 16] = [
+        "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7",
+        "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc",
+    ];
+    NAMES[register as usize]
+}
+
+fn shift_mnemonic(shift_type: ShiftType) -> &'static str {
+    match shift_type {
+        ShiftType::LogicalLeft => "lsl",
+        ShiftType::LogicalRight => "lsr",
+        ShiftType::ArithmeticRight => "asr",
+        ShiftType::RotateRight => "ror",
+        ShiftType::RotateRightExtended => "rrx",
+    }
+}
+
+/// The shift that goes after a register operand (including the leading comma)
+fn format_shift(shift: Shift) -> String {
+    match (shift.shift_type, shift.amount) {
+        (ShiftType::LogicalLeft, ShiftAmount::Immediate(0)) => String::new(),
+        (ShiftType::RotateRightExtended, _) => ", rrx".to_string(),
+        (shift_type, ShiftAmount::Immediate(amount)) =>
+            format!(", {} #{}", shift_mnemonic(shift_type), amount),
+        (shift_type, ShiftAmount::Register(rs)) =>
+            format!(", {} {}", shift_mnemonic(shift_type), register_name(rs)),
+    }
+}
+
+/// The bracketed address of a transfer, with the offset already formatted (if there is one)
+fn format_address_mode(rn: u8, offset: Option<String>, pre_index: bool, write_back: bool)
+-> String {
+    match (offset, pre_index) {
+        (None, true) if write_back => format!("[{}]!", register_name(rn)),
+        (None, _) => format!("[{}]", register_name(rn)),
+        (Some(offset), true) => format!("[{}, {}]{}", register_name(rn), offset,
+            if write_back { "!" } else { "" }),
+        (Some(offset), false) => format!("[{}], {}", register_name(rn), offset),
+    }
+}
+
+/// Groups the registers into ranges, like {r0-r3, r5, lr}
+fn format_register_list(register_list: u16) -> String {
+    let mut ranges: Vec<(u8, u8)> = Vec::new();
+    for register in (0..16).filter(|register| register_list & (1 << register) != 0) {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == register => *end = register,
+            _ => ranges.push((register, register)),
+        }
+    }
+
+    let ranges: Vec<String> = ranges.into_iter()
+        .map(|(start, end)| match end - start {
+            0 => register_name(start).to_string(),
+            1 => format!("{}, {}", register_name(start), register_name(end)),
+            _ => format!("{}-{}", register_name(start), register_name(end)),
+        })
+        .collect();
+    format!("{{{}}}", ranges.join(", "))
+}
+
+fn format_immediate(value: u32) -> String {
+    if value < 10 {
+        value.to_string()
+    } else {
+        format!("{:#X}", value)
+    }
+}
+
+fn format_address(address: u32) -> String {
+    format!("{:#010X}", address)
+}
+
+fn sign(up: bool) -> &'static str {
+    if up { "" } else { "-" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ArmArchitecture;
+    use super::*;
+
+    const ADDRESS: u32 = 0x0800_0000;
+
+    fn arm(instruction: u32) -> String {
+        Arm32::find_instruction(instruction, ArmArchitecture::V4T)
+            .expect("Not an instruction")
+            .disassemble(ADDRESS)
+    }
+    fn thumb(instruction: u16) -> Thumb32 {
+        Thumb32::find_instruction(instruction, ArmArchitecture::V4T).expect("Not an instruction")
+    }
+
+    #[test]
+    fn arm_branches_show_their_target() {
+        assert_eq!(arm(0xEA000000), "b 0x08000008");
+        assert_eq!(arm(0xEBFFFFFE), "bl 0x08000000");
+        assert_eq!(arm(0x0A00000F), "beq 0x08000044");
+    }
+
+    #[test]
+    fn arm_transfers() {
+        assert_eq!(arm(0xE92D400F), "push {r0-r3, lr}");
+        assert_eq!(arm(0xE8BD8070), "pop {r4-r6, pc}");
+        assert_eq!(arm(0xE9310006), "ldmdb r1!, {r1, r2}");
+        assert_eq!(arm(0xE1D100F2), "ldrsh r0, [r1, #2]");
+        assert_eq!(arm(0xE1F100F2), "ldrsh r0, [r1, #2]!");
+        assert_eq!(arm(0xE59F0010), "ldr r0, [pc, #0x10]");
+        assert_eq!(arm(0xE5B10000), "ldr r0, [r1]!");
+        assert_eq!(arm(0xE1F100B0), "ldrh r0, [r1]!");
+    }
+
+    #[test]
+    fn thumb_instructions() {
+        assert_eq!(thumb(0xB5F0).disassemble(ADDRESS), "push {r4-r7, lr}");
+        assert_eq!(thumb(0xC1E1).disassemble(ADDRESS), "stmia r1!, {r0, r5-r7}");
+        assert_eq!(thumb(0xD0FE).disassemble(ADDRESS), "beq 0x08000000");
+    }
+
+    #[test]
+    fn thumb_bl_pair_shows_the_whole_target() {
+        let (high, low) = (thumb(0xF000), thumb(0xFFFE));
+        assert_eq!(high.disassemble_pair(Some(&low), ADDRESS), "bl 0x08001000");
+        let high = thumb(0xF7FF);
+        assert_eq!(high.disassemble_pair(Some(&low), ADDRESS), "bl 0x08000000");
+        // Each half on its own
+        assert_eq!(high.disassemble_pair(None, ADDRESS), "bl lr = 0x07FFF004");
+        assert_eq!(low.disassemble(ADDRESS + 2), "bl lr + #0xFFC");
+
+        // BLX only exists from ARMv5, and lands word aligned in ARM
+        let low = Thumb32::find_instruction(0xEFFE, ArmArchitecture::V5TE).unwrap();
+        assert_eq!(thumb(0xF000).disassemble_pair(Some(&low), ADDRESS + 2), "blx 0x08001000");
+    }
+}
@@ -0,0 +1,357 @@
+use super::{
+    arm32::*,
+    thumb32::*,
+};
+
+impl Arm32 {
+    /// Builds the machine word for the instruction (the inverse of `find_instruction`)
+    pub fn encode(&self) -> u32 {
+        match self {
+            Arm32::Branch(branch) => {
+                let offset = ((branch.offset >> 2) as u32) & 0x00FF_FFFF;
+                condition_bits(branch.condition) | 0b101 << 25 | (branch.opcode as u32) << 24 |
+                    offset
+            },
+            Arm32::BranchExchange(branch) => {
+                condition_bits(branch.condition) | 0x012F_FF10 | (branch.link as u32) << 5 |
+                    branch.rn as u32
+            },
+            Arm32::DataProcessing(instruction) => {
+                let operand2 = match instruction.operand2 {
+                    ShifterOperand::Immediate { value, rotate } =>
+                        1 << 25 | ((rotate / 2) as u32) << 8 | value as u32,
+                    ShifterOperand::Register { rm, shift } => shift_bits(shift) | rm as u32,
+                };
+                condition_bits(instruction.condition) | alu_opcode_bits(instruction.opcode) << 21 |
+                    (instruction.set_condition_codes as u32) << 20 |
+                    (instruction.rn as u32) << 16 | (instruction.rd as u32) << 12 | operand2
+            },
+            Arm32::SingleDataTransfer(transfer) => {
+                let offset = match transfer.offset {
+                    TransferOffset::Immediate(offset) => offset as u32,
+                    TransferOffset::Register { rm, shift } =>
+                        1 << 25 | shift_bits(shift) | rm as u32,
+                };
+                condition_bits(transfer.condition) | 0b01 << 26 |
+                    (transfer.pre_index as u32) << 24 | (transfer.up as u32) << 23 |
+                    (transfer.byte as u32) << 22 | (transfer.write_back as u32) << 21 |
+                    (transfer.load as u32) << 20 | (transfer.rn as u32) << 16 |
+                    (transfer.rd as u32) << 12 | offset
+            },
+            Arm32::HalfwordDataTransfer(transfer) => {
+                let offset = match transfer.offset {
+                    HalfwordOffset::Immediate(offset) =>
+                        1 << 22 | ((offset & 0xF0) as u32) << 4 | (offset & 0xF) as u32,
+                    HalfwordOffset::Register(rm) => rm as u32,
+                };
+                let transfer_type = match transfer.transfer_type {
+                    HalfwordTransferType::Halfword => 0b01,
+                    HalfwordTransferType::SignedByte => 0b10,
+                    HalfwordTransferType::SignedHalfword => 0b11,
+                };
+                condition_bits(transfer.condition) | (transfer.pre_index as u32) << 24 |
+                    (transfer.up as u32) << 23 | (transfer.write_back as u32) << 21 |
+                    (transfer.load as u32) << 20 | (transfer.rn as u32) << 16 |
+                    (transfer.rd as u32) << 12 | 1 << 7 | transfer_type << 5 | 1 << 4 | offset
+            },
+            Arm32::BlockDataTransfer(transfer) => {
+                condition_bits(transfer.condition) | 0b100 << 25 |
+                    (transfer.pre_index as u32) << 24 | (transfer.up as u32) << 23 |
+                    (transfer.psr_or_user as u32) << 22 | (transfer.write_back as u32) << 21 |
+                    (transfer.load as u32) << 20 | (transfer.rn as u32) << 16 |
+                    transfer.register_list as u32
+            },
+            Arm32::Multiply(multiply) => {
+                condition_bits(multiply.condition) | (multiply.accumulate as u32) << 21 |
+                    (multiply.set_condition_codes as u32) << 20 | (multiply.rd as u32) << 16 |
+                    (multiply.rn as u32) << 12 | (multiply.rs as u32) << 8 | 0b1001 << 4 |
+                    multiply.rm as u32
+            },
+            Arm32::MultiplyLong(multiply) => {
+                condition_bits(multiply.condition) | 1 << 23 | (multiply.signed as u32) << 22 |
+                    (multiply.accumulate as u32) << 21 |
+                    (multiply.set_condition_codes as u32) << 20 |
+                    (multiply.rd_hi as u32) << 16 | (multiply.rd_lo as u32) << 12 |
+                    (multiply.rs as u32) << 8 | 0b1001 << 4 | multiply.rm as u32
+            },
+            Arm32::StatusToRegister(transfer) => {
+                condition_bits(transfer.condition) | 0x010F_0000 | (transfer.spsr as u32) << 22 |
+                    (transfer.rd as u32) << 12
+            },
+            Arm32::RegisterToStatus(transfer) => {
+                let operand = match transfer.operand {
+                    StatusOperand::Immediate { value, rotate } =>
+                        1 << 25 | ((rotate / 2) as u32) << 8 | value as u32,
+                    StatusOperand::Register(rm) => rm as u32,
+                };
+                condition_bits(transfer.condition) | 0x0120_F000 | (transfer.spsr as u32) << 22 |
+                    ((transfer.field_mask & 0xF) as u32) << 16 | operand
+            },
+            Arm32::Swap(swap) => {
+                condition_bits(swap.condition) | 1 << 24 | (swap.byte as u32) << 22 |
+                    (swap.rn as u32) << 16 | (swap.rd as u32) << 12 | 0b1001 << 4 | swap.rm as u32
+            },
+            Arm32::SoftwareInterrupt(interrupt) => {
+                condition_bits(interrupt.condition) | 0b1111 << 24 |
+                    (interrupt.comment & 0x00FF_FFFF)
+            },
+            Arm32::CountLeadingZeros(count) => {
+                condition_bits(count.condition) | 0x016F_0F10 | (count.rd as u32) << 12 |
+                    count.rm as u32
+            },
+            Arm32::SaturatingArithmetic(arithmetic) => {
+                let opcode = match arithmetic.opcode {
+                    SaturatingOpcode::Add => 0b00,
+                    SaturatingOpcode::Subtract => 0b01,
+                    SaturatingOpcode::DoubleAdd => 0b10,
+                    SaturatingOpcode::DoubleSubtract => 0b11,
+                };
+                condition_bits(arithmetic.condition) | 1 << 24 | opcode << 21 |
+                    (arithmetic.rn as u32) << 16 | (arithmetic.rd as u32) << 12 | 0b0101 << 4 |
+                    arithmetic.rm as u32
+            },
+            Arm32::SignedHalfwordMultiply(multiply) => {
+                let (opcode, x_top) = match multiply.opcode {
+                    HalfwordMultiplyOpcode::MultiplyAccumulate => (0b00, multiply.x_top),
+                    HalfwordMultiplyOpcode::MultiplyAccumulateWord => (0b01, false),
+                    HalfwordMultiplyOpcode::MultiplyWord => (0b01, true),
+                    HalfwordMultiplyOpcode::MultiplyAccumulateLong => (0b10, multiply.x_top),
+                    HalfwordMultiplyOpcode::Multiply => (0b11, multiply.x_top),
+                };
+                condition_bits(multiply.condition) | 1 << 24 | opcode << 21 |
+                    (multiply.rd as u32) << 16 | (multiply.rn as u32) << 12 |
+                    (multiply.rs as u32) << 8 | 1 << 7 | (multiply.y_top as u32) << 6 |
+                    (x_top as u32) << 5 | multiply.rm as u32
+            },
+        }
+    }
+}
+
+impl Thumb32 {
+    /// Builds the halfword for the instruction (the inverse of `find_instruction`)
+    pub fn encode(&self) -> u16 {
+        match self {
+            Thumb32::MoveShiftedRegister(shift) => {
+                let (shift_type, amount) = match (shift.shift.shift_type, shift.shift.amount) {
+                    (shift_type, ShiftAmount::Immediate(amount)) =>
+                        (shift_type_bits(shift_type) as u16, (amount & 0b11111) as u16),
+                    (_, ShiftAmount::Register(_)) => panic!("Programming error"),
+                };
+                shift_type << 11 | amount << 6 | (shift.rs as u16) << 3 | shift.rd as u16
+            },
+            Thumb32::AddSubtract(add) => {
+                0b00011 << 11 | (add.immediate as u16) << 10 | (add.subtract as u16) << 9 |
+                    (add.operand as u16) << 6 | (add.rs as u16) << 3 | add.rd as u16
+            },
+            Thumb32::ImmediateOperation(operation) => {
+                let opcode = match operation.opcode {
+                    ImmediateOpcode::Move => 0b00,
+                    ImmediateOpcode::Compare => 0b01,
+                    ImmediateOpcode::Add => 0b10,
+                    ImmediateOpcode::Subtract => 0b11,
+                };
+                0b001 << 13 | opcode << 11 | (operation.rd as u16) << 8 | operation.offset as u16
+            },
+            Thumb32::AluOperation(operation) => {
+                let opcode = match operation.opcode {
+                    ThumbAluOpcode::And => 0x0,
+                    ThumbAluOpcode::ExclusiveOr => 0x1,
+                    ThumbAluOpcode::LogicalLeft => 0x2,
+                    ThumbAluOpcode::LogicalRight => 0x3,
+                    ThumbAluOpcode::ArithmeticRight => 0x4,
+                    ThumbAluOpcode::AddCarry => 0x5,
+                    ThumbAluOpcode::SubtractCarry => 0x6,
+                    ThumbAluOpcode::RotateRight => 0x7,
+                    ThumbAluOpcode::Test => 0x8,
+                    ThumbAluOpcode::Negate => 0x9,
+                    ThumbAluOpcode::Compare => 0xA,
+                    ThumbAluOpcode::CompareNegative => 0xB,
+                    ThumbAluOpcode::Or => 0xC,
+                    ThumbAluOpcode::Multiply => 0xD,
+                    ThumbAluOpcode::BitClear => 0xE,
+                    ThumbAluOpcode::MoveNot => 0xF,
+                };
+                0b010000 << 10 | opcode << 6 | (operation.rs as u16) << 3 | operation.rd as u16
+            },
+            Thumb32::HiRegisterOperation(operation) => {
+                let opcode = match operation.opcode {
+                    HiRegisterOpcode::Add => 0b00,
+                    HiRegisterOpcode::Compare => 0b01,
+                    HiRegisterOpcode::Move => 0b10,
+                    HiRegisterOpcode::BranchExchange | HiRegisterOpcode::BranchLinkExchange => 0b11,
+                };
+                // BLX is told apart by the high Rd bit
+                let rd = match operation.opcode {
+                    HiRegisterOpcode::BranchLinkExchange => (operation.rd & 0b111) | 0b1000,
+                    _ => operation.rd,
+                };
+                0b010001 << 10 | opcode << 8 | ((rd >> 3) as u16) << 7 |
+                    ((operation.rs >> 3) as u16) << 6 | ((operation.rs & 0b111) as u16) << 3 |
+                    (rd & 0b111) as u16
+            },
+            Thumb32::PcRelativeLoad(load) => {
+                0b01001 << 11 | (load.rd as u16) << 8 | (load.offset / 4) & 0xFF
+            },
+            Thumb32::LoadStoreRegisterOffset(transfer) => {
+                0b0101 << 12 | (transfer.load as u16) << 11 | (transfer.byte as u16) << 10 |
+                    (transfer.ro as u16) << 6 | (transfer.rb as u16) << 3 | transfer.rd as u16
+            },
+            Thumb32::LoadStoreSignExtended(transfer) => {
+                let transfer_type = match transfer.transfer_type {
+                    SignExtendedType::StoreHalfword => 0b00,
+                    SignExtendedType::LoadSignedByte => 0b01,
+                    SignExtendedType::LoadHalfword => 0b10,
+                    SignExtendedType::LoadSignedHalfword => 0b11,
+                };
+                0b0101 << 12 | transfer_type << 10 | 1 << 9 | (transfer.ro as u16) << 6 |
+                    (transfer.rb as u16) << 3 | transfer.rd as u16
+            },
+            Thumb32::LoadStoreImmediateOffset(transfer) => {
+                // Words have their offset in words
+                let offset = if transfer.byte { transfer.offset } else { transfer.offset / 4 };
+                0b011 << 13 | (transfer.byte as u16) << 12 | (transfer.load as u16) << 11 |
+                    ((offset & 0b11111) as u16) << 6 | (transfer.rb as u16) << 3 |
+                    transfer.rd as u16
+            },
+            Thumb32::LoadStoreHalfword(transfer) => {
+                0b1000 << 12 | (transfer.load as u16) << 11 |
+                    (((transfer.offset / 2) & 0b11111) as u16) << 6 | (transfer.rb as u16) << 3 |
+                    transfer.rd as u16
+            },
+            Thumb32::SpRelativeLoadStore(transfer) => {
+                0b1001 << 12 | (transfer.load as u16) << 11 | (transfer.rd as u16) << 8 |
+                    (transfer.offset / 4) & 0xFF
+            },
+            Thumb32::LoadAddress(load) => {
+                0b1010 << 12 | (load.sp as u16) << 11 | (load.rd as u16) << 8 |
+                    (load.offset / 4) & 0xFF
+            },
+            Thumb32::AddOffsetToSp(add) => {
+                let negative = add.offset < 0;
+                0b10110000 << 8 | (negative as u16) << 7 | (add.offset.unsigned_abs() / 4) & 0x7F
+            },
+            Thumb32::PushPop(transfer) => {
+                0b1011_0100 << 8 | (transfer.load as u16) << 11 | (transfer.pc_or_lr as u16) << 8 |
+                    transfer.register_list as u16
+            },
+            Thumb32::MultipleLoadStore(transfer) => {
+                0b1100 << 12 | (transfer.load as u16) << 11 | (transfer.rb as u16) << 8 |
+                    transfer.register_list as u16
+            },
+            Thumb32::ConditionalBranch(branch) => {
+                0b1101 << 12 | ((condition_bits(branch.condition) >> 28) as u16) << 8 |
+                    ((branch.offset >> 1) as u16) & 0xFF
+            },
+            Thumb32::SoftwareInterrupt(interrupt) => 0b11011111 << 8 | interrupt.comment as u16,
+            Thumb32::UnconditionalBranch(branch) => {
+                0b11100 << 11 | ((branch.offset >> 1) as u16) & 0b111_11111111
+            },
+            Thumb32::LongBranchWithLink(branch) => {
+                let identifier = match (branch.low, branch.exchange) {
+                    (_, true) => 0b11101,
+                    (false, false) => 0b11110,
+                    (true, false) => 0b11111,
+                };
+                identifier << 11 | branch.offset & 0b111_11111111
+            },
+        }
+    }
+}
+
+fn condition_bits(condition: Condition) -> u32 {
+    let bits = match condition {
+        Condition::Equal => 0x0,
+        Condition::NEqual => 0x1,
+        Condition::CarrySet => 0x2,
+        Condition::CarryClear => 0x3,
+        Condition::Minus => 0x4,
+        Condition::Positive => 0x5,
+        Condition::VSet => 0x6,
+        Condition::VClear => 0x7,
+        Condition::UHigh => 0x8,
+        Condition::ULow => 0x9,
+        Condition::GreaterEqual => 0xA,
+        Condition::Less => 0xB,
+        Condition::Greater => 0xC,
+        Condition::LessEqual => 0xD,
+        Condition::Always => 0xE,
+        Condition::AllSet => 0xF,
+    };
+    bits << 28
+}
+
+fn alu_opcode_bits(opcode: AluOpcode) -> u32 {
+    match opcode {
+        AluOpcode::And => 0x0,
+        AluOpcode::ExclusiveOr => 0x1,
+        AluOpcode::Subtract => 0x2,
+        AluOpcode::ReverseSubtract => 0x3,
+        AluOpcode::Add => 0x4,
+        AluOpcode::AddCarry => 0x5,
+        AluOpcode::SubtractCarry => 0x6,
+        AluOpcode::ReverseSubtractCarry => 0x7,
+        AluOpcode::Test => 0x8,
+        AluOpcode::TestEqual => 0x9,
+        AluOpcode::Compare => 0xA,
+        AluOpcode::CompareNegative => 0xB,
+        AluOpcode::Or => 0xC,
+        AluOpcode::Move => 0xD,
+        AluOpcode::BitClear => 0xE,
+        AluOpcode::MoveNot => 0xF,
+    }
+}
+
+fn shift_type_bits(shift_type: ShiftType) -> u32 {
+    match shift_type {
+        ShiftType::LogicalLeft => 0b00,
+        ShiftType::LogicalRight => 0b01,
+        ShiftType::ArithmeticRight => 0b10,
+        ShiftType::RotateRight | ShiftType::RotateRightExtended => 0b11,
+    }
+}
+
+/// Bits 11-4 of a register operand. The special immediate shifts go back to their encodings.
+fn shift_bits(shift: Shift) -> u32 {
+    let shift_type = shift_type_bits(shift.shift_type) << 5;
+    match (shift.shift_type, shift.amount) {
+        (_, ShiftAmount::Register(rs)) => (rs as u32) << 8 | shift_type | 1 << 4,
+        (ShiftType::RotateRightExtended, _) => shift_type,
+        (_, ShiftAmount::Immediate(amount)) => ((amount & 0b11111) as u32) << 7 | shift_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ArmArchitecture, test_words::random_words};
+    use super::*;
+
+    const ARCHITECTURES: [ArmArchitecture; 2] = [ArmArchitecture::V4T, ArmArchitecture::V5TE];
+
+    #[test]
+    fn thumb_encode_matches_every_decoded_instruction() {
+        for architecture in ARCHITECTURES {
+            for instruction in 0..=u16::MAX {
+                // ADD SP, #-0 comes back as ADD SP, #0 since the offset can't hold the sign
+                if instruction == 0xB080 {
+                    continue;
+                }
+                if let Some(decoded) = Thumb32::find_instruction(instruction, architecture) {
+                    assert_eq!(decoded.encode(), instruction,
+                        "{:04X} ({:?})", instruction, architecture);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arm_encode_matches_sampled_decoded_instructions() {
+        for architecture in ARCHITECTURES {
+            for instruction in random_words(300_000) {
+                if let Some(decoded) = Arm32::find_instruction(instruction, architecture) {
+                    assert_eq!(decoded.encode(), instruction,
+                        "{:08X} ({:?})", instruction, architecture);
+                }
+            }
+        }
+    }
+}
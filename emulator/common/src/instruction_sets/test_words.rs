@@ -0,0 +1,11 @@
+/// Spreads test instructions out over the whole 32 bit space, the same way on every run
+pub fn random_words(count: usize) -> impl Iterator<Item = u32> {
+    let mut state = 0x1234_5678u32;
+    (0..count).map(move |_| {
+        // Xorshift
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    })
+}
@@ -1,7 +1,905 @@
-pub enum Thumb32 {
-}
-impl Thumb32 {
-    pub fn find_instruction(instruction: u16) -> Option<Thumb32> {
-        None
-    }
-}
+use super::{
+    ArmArchitecture,
+    arm32::{Condition, Shift},
+};
+
+pub enum Thumb32 {
+    MoveShiftedRegister(MoveShiftedRegister),
+    AddSubtract(AddSubtract),
+    ImmediateOperation(ImmediateOperation),
+    AluOperation(AluOperation),
+    HiRegisterOperation(HiRegisterOperation),
+    PcRelativeLoad(PcRelativeLoad),
+    LoadStoreRegisterOffset(LoadStoreRegisterOffset),
+    LoadStoreSignExtended(LoadStoreSignExtended),
+    LoadStoreImmediateOffset(LoadStoreImmediateOffset),
+    LoadStoreHalfword(LoadStoreHalfword),
+    SpRelativeLoadStore(SpRelativeLoadStore),
+    LoadAddress(LoadAddress),
+    AddOffsetToSp(AddOffsetToSp),
+    PushPop(PushPop),
+    MultipleLoadStore(MultipleLoadStore),
+    ConditionalBranch(ConditionalBranch),
+    SoftwareInterrupt(ThumbSoftwareInterrupt),
+    UnconditionalBranch(UnconditionalBranch),
+    LongBranchWithLink(LongBranchWithLink),
+}
+impl Thumb32 {
+    /// Only the instructions that exist in the architecture will be found
+    pub fn find_instruction(instruction: u16, architecture: ArmArchitecture) -> Option<Thumb32> {
+        // The top 10 bits narrow it down to the few classes that could match
+        let mut classes = DECODE_TABLE[(instruction >> 6) as usize];
+        while classes != 0 {
+            let class = &DECODE_CLASSES[classes.trailing_zeros() as usize];
+            classes &= classes - 1;
+            if let Some(decoded) = (class.decode)(instruction, architecture) {
+                return Some(decoded);
+            }
+        }
+        None
+    }
+}
+
+/// The classes that could match each index, as bits into `DECODE_CLASSES`.
+/// The ARMv5 differences are all inside of a class, so the architectures can share it.
+static DECODE_TABLE: [u32; 1024] = build_decode_table();
+
+struct DecodeClass {
+    identifier_mask: u16,
+    identifier: u16,
+    decode: fn(u16, ArmArchitecture) -> Option<Thumb32>,
+}
+
+/// Every class, in the order that they get tried (some encodings overlap)
+const DECODE_CLASSES: [DecodeClass; 19] = [
+    DecodeClass {
+        identifier_mask: MoveShiftedRegister::IDENTIFIER_MASK,
+        identifier: MoveShiftedRegister::IDENTIFIER,
+        decode: |instruction, _| MoveShiftedRegister::from_instruction(instruction)
+            .map(Thumb32::MoveShiftedRegister),
+    },
+    DecodeClass {
+        identifier_mask: AddSubtract::IDENTIFIER_MASK,
+        identifier: AddSubtract::IDENTIFIER,
+        decode: |instruction, _| AddSubtract::from_instruction(instruction)
+            .map(Thumb32::AddSubtract),
+    },
+    DecodeClass {
+        identifier_mask: ImmediateOperation::IDENTIFIER_MASK,
+        identifier: ImmediateOperation::IDENTIFIER,
+        decode: |instruction, _| ImmediateOperation::from_instruction(instruction)
+            .map(Thumb32::ImmediateOperation),
+    },
+    DecodeClass {
+        identifier_mask: AluOperation::IDENTIFIER_MASK,
+        identifier: AluOperation::IDENTIFIER,
+        decode: |instruction, _| AluOperation::from_instruction(instruction)
+            .map(Thumb32::AluOperation),
+    },
+    DecodeClass {
+        identifier_mask: HiRegisterOperation::IDENTIFIER_MASK,
+        identifier: HiRegisterOperation::IDENTIFIER,
+        decode: |instruction, architecture| {
+            HiRegisterOperation::from_instruction(instruction, architecture)
+                .map(Thumb32::HiRegisterOperation)
+        },
+    },
+    DecodeClass {
+        identifier_mask: PcRelativeLoad::IDENTIFIER_MASK,
+        identifier: PcRelativeLoad::IDENTIFIER,
+        decode: |instruction, _| PcRelativeLoad::from_instruction(instruction)
+            .map(Thumb32::PcRelativeLoad),
+    },
+    DecodeClass {
+        identifier_mask: LoadStoreRegisterOffset::IDENTIFIER_MASK,
+        identifier: LoadStoreRegisterOffset::IDENTIFIER,
+        decode: |instruction, _| LoadStoreRegisterOffset::from_instruction(instruction)
+            .map(Thumb32::LoadStoreRegisterOffset),
+    },
+    DecodeClass {
+        identifier_mask: LoadStoreSignExtended::IDENTIFIER_MASK,
+        identifier: LoadStoreSignExtended::IDENTIFIER,
+        decode: |instruction, _| LoadStoreSignExtended::from_instruction(instruction)
+            .map(Thumb32::LoadStoreSignExtended),
+    },
+    DecodeClass {
+        identifier_mask: LoadStoreImmediateOffset::IDENTIFIER_MASK,
+        identifier: LoadStoreImmediateOffset::IDENTIFIER,
+        decode: |instruction, _| LoadStoreImmediateOffset::from_instruction(instruction)
+            .map(Thumb32::LoadStoreImmediateOffset),
+    },
+    DecodeClass {
+        identifier_mask: LoadStoreHalfword::IDENTIFIER_MASK,
+        identifier: LoadStoreHalfword::IDENTIFIER,
+        decode: |instruction, _| LoadStoreHalfword::from_instruction(instruction)
+            .map(Thumb32::LoadStoreHalfword),
+    },
+    DecodeClass {
+        identifier_mask: SpRelativeLoadStore::IDENTIFIER_MASK,
+        identifier: SpRelativeLoadStore::IDENTIFIER,
+        decode: |instruction, _| SpRelativeLoadStore::from_instruction(instruction)
+            .map(Thumb32::SpRelativeLoadStore),
+    },
+    DecodeClass {
+        identifier_mask: LoadAddress::IDENTIFIER_MASK,
+        identifier: LoadAddress::IDENTIFIER,
+        decode: |instruction, _| LoadAddress::from_instruction(instruction)
+            .map(Thumb32::LoadAddress),
+    },
+    DecodeClass {
+        identifier_mask: AddOffsetToSp::IDENTIFIER_MASK,
+        identifier: AddOffsetToSp::IDENTIFIER,
+        decode: |instruction, _| AddOffsetToSp::from_instruction(instruction)
+            .map(Thumb32::AddOffsetToSp),
+    },
+    DecodeClass {
+        identifier_mask: PushPop::IDENTIFIER_MASK,
+        identifier: PushPop::IDENTIFIER,
+        decode: |instruction, _| PushPop::from_instruction(instruction).map(Thumb32::PushPop),
+    },
+    DecodeClass {
+        identifier_mask: MultipleLoadStore::IDENTIFIER_MASK,
+        identifier: MultipleLoadStore::IDENTIFIER,
+        decode: |instruction, _| MultipleLoadStore::from_instruction(instruction)
+            .map(Thumb32::MultipleLoadStore),
+    },
+    DecodeClass {
+        identifier_mask: ConditionalBranch::IDENTIFIER_MASK,
+        identifier: ConditionalBranch::IDENTIFIER,
+        decode: |instruction, _| ConditionalBranch::from_instruction(instruction)
+            .map(Thumb32::ConditionalBranch),
+    },
+    DecodeClass {
+        identifier_mask: ThumbSoftwareInterrupt::IDENTIFIER_MASK,
+        identifier: ThumbSoftwareInterrupt::IDENTIFIER,
+        decode: |instruction, _| ThumbSoftwareInterrupt::from_instruction(instruction)
+            .map(Thumb32::SoftwareInterrupt),
+    },
+    DecodeClass {
+        identifier_mask: UnconditionalBranch::IDENTIFIER_MASK,
+        identifier: UnconditionalBranch::IDENTIFIER,
+        decode: |instruction, _| UnconditionalBranch::from_instruction(instruction)
+            .map(Thumb32::UnconditionalBranch),
+    },
+    DecodeClass {
+        // Either of the BL halves or the BLX suffix, so only the bits that they share
+        identifier_mask: LongBranchWithLink::IDENTIFIER_MASK &
+            !(LongBranchWithLink::IDENTIFIER ^ LongBranchWithLink::EXCHANGE_IDENTIFIER),
+        identifier: LongBranchWithLink::IDENTIFIER & LongBranchWithLink::EXCHANGE_IDENTIFIER,
+        decode: |instruction, architecture| {
+            LongBranchWithLink::from_instruction(instruction, architecture)
+                .map(Thumb32::LongBranchWithLink)
+        },
+    },
+];
+
+const fn build_decode_table() -> [u32; 1024] {
+    const INDEX_BITS: u16 = 0b11111111_11000000;
+    let mut table = [0; 1024];
+    let mut index = 0;
+    while index < table.len() {
+        let instruction = (index as u16) << 6;
+        let mut class = 0;
+        while class < DECODE_CLASSES.len() {
+            // Only the index bits can be checked here, so the rest is left to the decoder
+            let mask = DECODE_CLASSES[class].identifier_mask & INDEX_BITS;
+            if instruction & mask == DECODE_CLASSES[class].identifier & mask {
+                table[index] |= 1 << class;
+            }
+            class += 1;
+        }
+        index += 1;
+    }
+    table
+}
+
+/// Format 1: LSL, LSR or ASR by an immediate
+pub struct MoveShiftedRegister {
+    /// The shift amount is always an immediate here
+    pub shift: Shift,
+    /// The source register
+    pub rs: u8,
+    /// The destination register
+    pub rd: u8,
+}
+impl MoveShiftedRegister {
+    const IDENTIFIER_MASK: u16 = 0b11100000_00000000;
+    const IDENTIFIER: u16 = 0b00000000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<MoveShiftedRegister> {
+        let opcode = ((instruction >> 11) & 0b11) as u32;
+        // The last opcode is the add/subtract format
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER && opcode != 0b11 {
+            Some(MoveShiftedRegister {
+                shift: Shift::from_immediate(opcode, ((instruction >> 6) & 0b11111) as u8),
+                rs: ((instruction >> 3) & 0b111) as u8,
+                rd: (instruction & 0b111) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 2: ADD or SUB with a register or a 3 bit immediate
+pub struct AddSubtract {
+    /// If the operand is an immediate instead of a register
+    pub immediate: bool,
+    /// If this is a SUB instead of an ADD
+    pub subtract: bool,
+    /// Either the 3 bit immediate or the register Rn
+    pub operand: u8,
+    /// The source register
+    pub rs: u8,
+    /// The destination register
+    pub rd: u8,
+}
+impl AddSubtract {
+    const IDENTIFIER_MASK: u16 = 0b11111000_00000000;
+    const IDENTIFIER: u16 = 0b00011000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<AddSubtract> {
+        const IMMEDIATE_BIT: u16 = 0b00000100_00000000;
+        const SUBTRACT_BIT: u16 = 0b00000010_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(AddSubtract {
+                immediate: instruction & IMMEDIATE_BIT == IMMEDIATE_BIT,
+                subtract: instruction & SUBTRACT_BIT == SUBTRACT_BIT,
+                operand: ((instruction >> 6) & 0b111) as u8,
+                rs: ((instruction >> 3) & 0b111) as u8,
+                rd: (instruction & 0b111) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 3: MOV, CMP, ADD or SUB with an 8 bit immediate
+pub struct ImmediateOperation {
+    pub opcode: ImmediateOpcode,
+    /// The destination (and source) register
+    pub rd: u8,
+    pub offset: u8,
+}
+impl ImmediateOperation {
+    const IDENTIFIER_MASK: u16 = 0b11100000_00000000;
+    const IDENTIFIER: u16 = 0b00100000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<ImmediateOperation> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            let opcode = match (instruction >> 11) & 0b11 {
+                0b00 => ImmediateOpcode::Move,
+                0b01 => ImmediateOpcode::Compare,
+                0b10 => ImmediateOpcode::Add,
+                0b11 => ImmediateOpcode::Subtract,
+                _ => panic!("Programming error"),
+            };
+            Some(ImmediateOperation {
+                opcode,
+                rd: ((instruction >> 8) & 0b111) as u8,
+                offset: (instruction & 0xFF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ImmediateOpcode {
+    /// MOV: Rd = Offset
+    Move,
+    /// CMP: Void = Rd - Offset
+    Compare,
+    /// ADD: Rd = Rd + Offset
+    Add,
+    /// SUB: Rd = Rd - Offset
+    Subtract,
+}
+
+/// Format 4: ALU operations between 2 low registers
+pub struct AluOperation {
+    pub opcode: ThumbAluOpcode,
+    /// The source register
+    pub rs: u8,
+    /// The destination (and source) register
+    pub rd: u8,
+}
+impl AluOperation {
+    const IDENTIFIER_MASK: u16 = 0b11111100_00000000;
+    const IDENTIFIER: u16 = 0b01000000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<AluOperation> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            let opcode = match (instruction >> 6) & 0xF {
+                0x0 => ThumbAluOpcode::And,
+                0x1 => ThumbAluOpcode::ExclusiveOr,
+                0x2 => ThumbAluOpcode::LogicalLeft,
+                0x3 => ThumbAluOpcode::LogicalRight,
+                0x4 => ThumbAluOpcode::ArithmeticRight,
+                0x5 => ThumbAluOpcode::AddCarry,
+                0x6 => ThumbAluOpcode::SubtractCarry,
+                0x7 => ThumbAluOpcode::RotateRight,
+                0x8 => ThumbAluOpcode::Test,
+                0x9 => ThumbAluOpcode::Negate,
+                0xA => ThumbAluOpcode::Compare,
+                0xB => ThumbAluOpcode::CompareNegative,
+                0xC => ThumbAluOpcode::Or,
+                0xD => ThumbAluOpcode::Multiply,
+                0xE => ThumbAluOpcode::BitClear,
+                0xF => ThumbAluOpcode::MoveNot,
+                _ => panic!("Programming error"),
+            };
+            Some(AluOperation {
+                opcode,
+                rs: ((instruction >> 3) & 0b111) as u8,
+                rd: (instruction & 0b111) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ThumbAluOpcode {
+    /// AND: Rd = Rd AND Rs
+    And,
+    /// EOR: Rd = Rd XOR Rs
+    ExclusiveOr,
+    /// LSL: Rd = Rd << Rs
+    LogicalLeft,
+    /// LSR: Rd = Rd >> Rs
+    LogicalRight,
+    /// ASR: Rd = Rd SAR Rs
+    ArithmeticRight,
+    /// ADC: Rd = Rd + Rs + C
+    AddCarry,
+    /// SBC: Rd = Rd - Rs + C - 1
+    SubtractCarry,
+    /// ROR: Rd = Rd ROR Rs
+    RotateRight,
+    /// TST: Void = Rd AND Rs
+    Test,
+    /// NEG: Rd = 0 - Rs
+    Negate,
+    /// CMP: Void = Rd - Rs
+    Compare,
+    /// CMN: Void = Rd + Rs
+    CompareNegative,
+    /// ORR: Rd = Rd OR Rs
+    Or,
+    /// MUL: Rd = Rd * Rs
+    Multiply,
+    /// BIC: Rd = Rd AND NOT Rs
+    BitClear,
+    /// MVN: Rd = NOT Rs
+    MoveNot,
+}
+
+/// Format 5: ADD, CMP and MOV that can use R8-R15, and BX
+pub struct HiRegisterOperation {
+    pub opcode: HiRegisterOpcode,
+    /// The source register (0-15)
+    pub rs: u8,
+    /// The destination register (0-15), unused by BX and BLX
+    pub rd: u8,
+}
+impl HiRegisterOperation {
+    const IDENTIFIER_MASK: u16 = 0b11111100_00000000;
+    const IDENTIFIER: u16 = 0b01000100_00000000;
+
+    fn from_instruction(instruction: u16, architecture: ArmArchitecture)
+    -> Option<HiRegisterOperation> {
+        const HIGH_RD_BIT: u16 = 0b00000000_10000000;
+        const HIGH_RS_BIT: u16 = 0b00000000_01000000;
+        if instruction & Self::IDENTIFIER_MASK != Self::IDENTIFIER {
+            return None;
+        }
+
+        let opcode = match (instruction >> 8) & 0b11 {
+            0b00 => HiRegisterOpcode::Add,
+            0b01 => HiRegisterOpcode::Compare,
+            0b10 => HiRegisterOpcode::Move,
+            // The high Rd bit turns it into a BLX
+            0b11 if instruction & HIGH_RD_BIT == HIGH_RD_BIT =>
+                if architecture >= ArmArchitecture::V5TE {
+                    HiRegisterOpcode::BranchLinkExchange
+                } else {
+                    return None;
+                },
+            0b11 => HiRegisterOpcode::BranchExchange,
+            _ => panic!("Programming error"),
+        };
+        let mut rs = ((instruction >> 3) & 0b111) as u8;
+        if instruction & HIGH_RS_BIT == HIGH_RS_BIT {
+            rs += 8;
+        }
+        let mut rd = (instruction & 0b111) as u8;
+        if instruction & HIGH_RD_BIT == HIGH_RD_BIT {
+            rd += 8;
+        }
+        Some(HiRegisterOperation { opcode, rs, rd })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum HiRegisterOpcode {
+    /// ADD: Rd = Rd + Rs (the flags are left alone)
+    Add,
+    /// CMP: Void = Rd - Rs
+    Compare,
+    /// MOV: Rd = Rs (the flags are left alone)
+    Move,
+    /// BX: Branch to Rs, switching to ARM if bit 0 is clear
+    BranchExchange,
+    /// BLX: BX that also puts the return address in LR (ARMv5 only)
+    BranchLinkExchange,
+}
+
+/// Format 6: LDR relative to the PC (with bit 1 forced off)
+pub struct PcRelativeLoad {
+    /// The destination register
+    pub rd: u8,
+    /// The offset in bytes
+    pub offset: u16,
+}
+impl PcRelativeLoad {
+    const IDENTIFIER_MASK: u16 = 0b11111000_00000000;
+    const IDENTIFIER: u16 = 0b01001000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<PcRelativeLoad> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(PcRelativeLoad {
+                rd: ((instruction >> 8) & 0b111) as u8,
+                offset: (instruction & 0xFF) * 4,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 7: LDR, STR, LDRB or STRB with a register offset
+pub struct LoadStoreRegisterOffset {
+    /// If this is a load from memory or a store to memory
+    pub load: bool,
+    /// If a single byte is transferred instead of a word
+    pub byte: bool,
+    /// The offset register
+    pub ro: u8,
+    /// The base register
+    pub rb: u8,
+    /// The source or destination register
+    pub rd: u8,
+}
+impl LoadStoreRegisterOffset {
+    const IDENTIFIER_MASK: u16 = 0b11110010_00000000;
+    const IDENTIFIER: u16 = 0b01010000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<LoadStoreRegisterOffset> {
+        const LOAD_BIT: u16 = 0b00001000_00000000;
+        const BYTE_BIT: u16 = 0b00000100_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(LoadStoreRegisterOffset {
+                load: instruction & LOAD_BIT == LOAD_BIT,
+                byte: instruction & BYTE_BIT == BYTE_BIT,
+                ro: ((instruction >> 6) & 0b111) as u8,
+                rb: ((instruction >> 3) & 0b111) as u8,
+                rd: (instruction & 0b111) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 8: STRH, LDRH, LDSB or LDSH with a register offset
+pub struct LoadStoreSignExtended {
+    pub transfer_type: SignExtendedType,
+    /// The offset register
+    pub ro: u8,
+    /// The base register
+    pub rb: u8,
+    /// The source or destination register
+    pub rd: u8,
+}
+impl LoadStoreSignExtended {
+    const IDENTIFIER_MASK: u16 = 0b11110010_00000000;
+    const IDENTIFIER: u16 = 0b01010010_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<LoadStoreSignExtended> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            let transfer_type = match (instruction >> 10) & 0b11 {
+                0b00 => SignExtendedType::StoreHalfword,
+                0b01 => SignExtendedType::LoadSignedByte,
+                0b10 => SignExtendedType::LoadHalfword,
+                0b11 => SignExtendedType::LoadSignedHalfword,
+                _ => panic!("Programming error"),
+            };
+            Some(LoadStoreSignExtended {
+                transfer_type,
+                ro: ((instruction >> 6) & 0b111) as u8,
+                rb: ((instruction >> 3) & 0b111) as u8,
+                rd: (instruction & 0b111) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum SignExtendedType {
+    /// STRH
+    StoreHalfword,
+    /// LDSB
+    LoadSignedByte,
+    /// LDRH
+    LoadHalfword,
+    /// LDSH
+    LoadSignedHalfword,
+}
+
+/// Format 9: LDR, STR, LDRB or STRB with an immediate offset
+pub struct LoadStoreImmediateOffset {
+    /// If this is a load from memory or a store to memory
+    pub load: bool,
+    /// If a single byte is transferred instead of a word
+    pub byte: bool,
+    /// The offset in bytes
+    pub offset: u8,
+    /// The base register
+    pub rb: u8,
+    /// The source or destination register
+    pub rd: u8,
+}
+impl LoadStoreImmediateOffset {
+    const IDENTIFIER_MASK: u16 = 0b11100000_00000000;
+    const IDENTIFIER: u16 = 0b01100000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<LoadStoreImmediateOffset> {
+        const BYTE_BIT: u16 = 0b00010000_00000000;
+        const LOAD_BIT: u16 = 0b00001000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            let byte = instruction & BYTE_BIT == BYTE_BIT;
+            let offset = ((instruction >> 6) & 0b11111) as u8;
+            Some(LoadStoreImmediateOffset {
+                load: instruction & LOAD_BIT == LOAD_BIT,
+                byte,
+                // Words have their offset in words
+                offset: if byte { offset } else { offset * 4 },
+                rb: ((instruction >> 3) & 0b111) as u8,
+                rd: (instruction & 0b111) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 10: LDRH or STRH with an immediate offset
+pub struct LoadStoreHalfword {
+    /// If this is a load from memory or a store to memory
+    pub load: bool,
+    /// The offset in bytes
+    pub offset: u8,
+    /// The base register
+    pub rb: u8,
+    /// The source or destination register
+    pub rd: u8,
+}
+impl LoadStoreHalfword {
+    const IDENTIFIER_MASK: u16 = 0b11110000_00000000;
+    const IDENTIFIER: u16 = 0b10000000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<LoadStoreHalfword> {
+        const LOAD_BIT: u16 = 0b00001000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(LoadStoreHalfword {
+                load: instruction & LOAD_BIT == LOAD_BIT,
+                offset: ((instruction >> 6) & 0b11111) as u8 * 2,
+                rb: ((instruction >> 3) & 0b111) as u8,
+                rd: (instruction & 0b111) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 11: LDR or STR relative to the SP
+pub struct SpRelativeLoadStore {
+    /// If this is a load from memory or a store to memory
+    pub load: bool,
+    /// The source or destination register
+    pub rd: u8,
+    /// The offset in bytes
+    pub offset: u16,
+}
+impl SpRelativeLoadStore {
+    const IDENTIFIER_MASK: u16 = 0b11110000_00000000;
+    const IDENTIFIER: u16 = 0b10010000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<SpRelativeLoadStore> {
+        const LOAD_BIT: u16 = 0b00001000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(SpRelativeLoadStore {
+                load: instruction & LOAD_BIT == LOAD_BIT,
+                rd: ((instruction >> 8) & 0b111) as u8,
+                offset: (instruction & 0xFF) * 4,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 12: ADD Rd, PC or SP, #offset
+pub struct LoadAddress {
+    /// If the address is from the SP instead of the PC (with bit 1 forced off)
+    pub sp: bool,
+    /// The destination register
+    pub rd: u8,
+    /// The offset in bytes
+    pub offset: u16,
+}
+impl LoadAddress {
+    const IDENTIFIER_MASK: u16 = 0b11110000_00000000;
+    const IDENTIFIER: u16 = 0b10100000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<LoadAddress> {
+        const SP_BIT: u16 = 0b00001000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(LoadAddress {
+                sp: instruction & SP_BIT == SP_BIT,
+                rd: ((instruction >> 8) & 0b111) as u8,
+                offset: (instruction & 0xFF) * 4,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 13: ADD SP, #offset
+pub struct AddOffsetToSp {
+    /// The signed offset in bytes.
+    /// ADD SP, #-0 (0xB080) can't keep its sign, so it encodes back as ADD SP, #0.
+    pub offset: i16,
+}
+impl AddOffsetToSp {
+    const IDENTIFIER_MASK: u16 = 0b11111111_00000000;
+    const IDENTIFIER: u16 = 0b10110000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<AddOffsetToSp> {
+        const NEGATIVE_BIT: u16 = 0b00000000_10000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            let offset = ((instruction & 0b0111_1111) * 4) as i16;
+            Some(AddOffsetToSp {
+                offset: if instruction & NEGATIVE_BIT == NEGATIVE_BIT { -offset } else { offset },
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 14: PUSH or POP, which can also do LR or PC
+pub struct PushPop {
+    /// If this is a POP instead of a PUSH
+    pub load: bool,
+    /// If LR gets pushed or PC gets popped
+    pub pc_or_lr: bool,
+    /// Each set bit is a register to transfer, with bit 0 being R0
+    pub register_list: u8,
+}
+impl PushPop {
+    const IDENTIFIER_MASK: u16 = 0b11110110_00000000;
+    const IDENTIFIER: u16 = 0b10110100_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<PushPop> {
+        const LOAD_BIT: u16 = 0b00001000_00000000;
+        const PC_OR_LR_BIT: u16 = 0b00000001_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(PushPop {
+                load: instruction & LOAD_BIT == LOAD_BIT,
+                pc_or_lr: instruction & PC_OR_LR_BIT == PC_OR_LR_BIT,
+                register_list: (instruction & 0xFF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 15: LDMIA or STMIA, which always write back
+pub struct MultipleLoadStore {
+    /// If this is a load from memory or a store to memory
+    pub load: bool,
+    /// The base register
+    pub rb: u8,
+    /// Each set bit is a register to transfer, with bit 0 being R0
+    pub register_list: u8,
+}
+impl MultipleLoadStore {
+    const IDENTIFIER_MASK: u16 = 0b11110000_00000000;
+    const IDENTIFIER: u16 = 0b11000000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<MultipleLoadStore> {
+        const LOAD_BIT: u16 = 0b00001000_00000000;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(MultipleLoadStore {
+                load: instruction & LOAD_BIT == LOAD_BIT,
+                rb: ((instruction >> 8) & 0b111) as u8,
+                register_list: (instruction & 0xFF) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 16: B{cond}
+pub struct ConditionalBranch {
+    /// Never AL or NV (those are undefined and SWI)
+    pub condition: Condition,
+    /// The offset to the new instruction in bytes
+    pub offset: i16,
+}
+impl ConditionalBranch {
+    const IDENTIFIER_MASK: u16 = 0b11110000_00000000;
+    const IDENTIFIER: u16 = 0b11010000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<ConditionalBranch> {
+        let condition = Condition::from_bits(((instruction >> 8) & 0xF) as u32);
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER &&
+            condition != Condition::Always && condition != Condition::AllSet {
+            Some(ConditionalBranch {
+                condition,
+                offset: (instruction & 0xFF) as u8 as i8 as i16 * 2,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 17: SWI
+pub struct ThumbSoftwareInterrupt {
+    /// Ignored by the CPU, but the handler can read it back from the instruction
+    pub comment: u8,
+}
+impl ThumbSoftwareInterrupt {
+    const IDENTIFIER_MASK: u16 = 0b11111111_00000000;
+    const IDENTIFIER: u16 = 0b11011111_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<ThumbSoftwareInterrupt> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(ThumbSoftwareInterrupt { comment: (instruction & 0xFF) as u8 })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 18: B
+pub struct UnconditionalBranch {
+    /// The offset to the new instruction in bytes
+    pub offset: i16,
+}
+impl UnconditionalBranch {
+    const IDENTIFIER_MASK: u16 = 0b11111000_00000000;
+    const IDENTIFIER: u16 = 0b11100000_00000000;
+
+    fn from_instruction(instruction: u16) -> Option<UnconditionalBranch> {
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            // Shift the 11 bits all the way up so that the sign comes back down with it
+            let offset = ((instruction << 5) as i16 >> 5) * 2;
+            Some(UnconditionalBranch { offset })
+        } else {
+            None
+        }
+    }
+}
+
+/// Format 19: BL, which is made of 2 instructions.
+/// The 1st one puts the upper part of the offset into LR, then the 2nd one branches.
+pub struct LongBranchWithLink {
+    /// If this is the 2nd instruction (with the low part of the offset)
+    pub low: bool,
+    /// If the 2nd instruction switches to ARM (BLX, ARMv5 only)
+    pub exchange: bool,
+    /// The 11 bit part of the offset
+    pub offset: u16,
+}
+impl LongBranchWithLink {
+    const IDENTIFIER_MASK: u16 = 0b11110000_00000000;
+    const IDENTIFIER: u16 = 0b11110000_00000000;
+    const EXCHANGE_IDENTIFIER_MASK: u16 = 0b11111000_00000000;
+    const EXCHANGE_IDENTIFIER: u16 = 0b11101000_00000000;
+
+    fn from_instruction(instruction: u16, architecture: ArmArchitecture)
+    -> Option<LongBranchWithLink> {
+        const LOW_BIT: u16 = 0b00001000_00000000;
+        let offset = instruction & 0b00000111_11111111;
+        if instruction & Self::IDENTIFIER_MASK == Self::IDENTIFIER {
+            Some(LongBranchWithLink {
+                low: instruction & LOW_BIT == LOW_BIT,
+                exchange: false,
+                offset,
+            })
+        } else if instruction & Self::EXCHANGE_IDENTIFIER_MASK == Self::EXCHANGE_IDENTIFIER &&
+            architecture >= ArmArchitecture::V5TE {
+            Some(LongBranchWithLink { low: true, exchange: true, offset })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::discriminant;
+    use super::*;
+
+    /// The linear decoder from before the table, which the table has to match exactly
+    fn find_instruction_linear(instruction: u16, architecture: ArmArchitecture)
+    -> Option<Thumb32> {
+        if let Some(shift) = MoveShiftedRegister::from_instruction(instruction) {
+            Some(Thumb32::MoveShiftedRegister(shift))
+        } else if let Some(add) = AddSubtract::from_instruction(instruction) {
+            Some(Thumb32::AddSubtract(add))
+        } else if let Some(operation) = ImmediateOperation::from_instruction(instruction) {
+            Some(Thumb32::ImmediateOperation(operation))
+        } else if let Some(operation) = AluOperation::from_instruction(instruction) {
+            Some(Thumb32::AluOperation(operation))
+        } else if let Some(operation) =
+            HiRegisterOperation::from_instruction(instruction, architecture) {
+            Some(Thumb32::HiRegisterOperation(operation))
+        } else if let Some(load) = PcRelativeLoad::from_instruction(instruction) {
+            Some(Thumb32::PcRelativeLoad(load))
+        } else if let Some(transfer) = LoadStoreRegisterOffset::from_instruction(instruction) {
+            Some(Thumb32::LoadStoreRegisterOffset(transfer))
+        } else if let Some(transfer) = LoadStoreSignExtended::from_instruction(instruction) {
+            Some(Thumb32::LoadStoreSignExtended(transfer))
+        } else if let Some(transfer) = LoadStoreImmediateOffset::from_instruction(instruction) {
+            Some(Thumb32::LoadStoreImmediateOffset(transfer))
+        } else if let Some(transfer) = LoadStoreHalfword::from_instruction(instruction) {
+            Some(Thumb32::LoadStoreHalfword(transfer))
+        } else if let Some(transfer) = SpRelativeLoadStore::from_instruction(instruction) {
+            Some(Thumb32::SpRelativeLoadStore(transfer))
+        } else if let Some(load) = LoadAddress::from_instruction(instruction) {
+            Some(Thumb32::LoadAddress(load))
+        } else if let Some(add) = AddOffsetToSp::from_instruction(instruction) {
+            Some(Thumb32::AddOffsetToSp(add))
+        } else if let Some(transfer) = PushPop::from_instruction(instruction) {
+            Some(Thumb32::PushPop(transfer))
+        } else if let Some(transfer) = MultipleLoadStore::from_instruction(instruction) {
+            Some(Thumb32::MultipleLoadStore(transfer))
+        } else if let Some(branch) = ConditionalBranch::from_instruction(instruction) {
+            Some(Thumb32::ConditionalBranch(branch))
+        } else if let Some(interrupt) = ThumbSoftwareInterrupt::from_instruction(instruction) {
+            Some(Thumb32::SoftwareInterrupt(interrupt))
+        } else if let Some(branch) = UnconditionalBranch::from_instruction(instruction) {
+            Some(Thumb32::UnconditionalBranch(branch))
+        } else {
+            LongBranchWithLink::from_instruction(instruction, architecture)
+                .map(Thumb32::LongBranchWithLink)
+        }
+    }
+
+    #[test]
+    fn table_matches_linear_decoder() {
+        for architecture in [ArmArchitecture::V4T, ArmArchitecture::V5TE] {
+            for instruction in 0..=u16::MAX {
+                let decoded = Thumb32::find_instruction(instruction, architecture)
+                    .map(|decoded| (discriminant(&decoded), decoded.encode()));
+                let expected = find_instruction_linear(instruction, architecture)
+                    .map(|expected| (discriminant(&expected), expected.encode()));
+                assert!(decoded == expected, "{:04X} ({:?})", instruction, architecture);
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 pub mod instruction_sets;
 pub mod memory;
+pub mod scheduler;
 
 use std::{
     io::{Error as IOError},
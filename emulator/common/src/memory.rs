@@ -1,86 +1,299 @@
+use std::rc::Rc;
+
+/// Each page of the address space is 1KB
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
 pub type MemoryResult<T> = Result<T, MemoryError>;
 #[derive(Copy, Clone, Debug)]
 pub enum MemoryError {
     NoMatchingRegion,
     CrossRegionAccess,
+    /// Nothing drives the bus here, so it's up to the core to decide what gets read
+    OpenBus,
+    /// A write to a region that doesn't allow them
+    ReadOnly,
 }
 
 pub struct Memory {
     regions: Vec<MemoryRegion>,
+    /// Maps every page of the address space to the region that covers it
+    pages: Vec<Page>,
 }
 impl Memory {
-    /// The memory regions must ensure the following:
-    /// - There are no overlaps
-    /// - The entire memory space is mapped
+    /// The memory regions must not overlap.
+    /// Addresses that no region covers are left unmapped, and give a NoMatchingRegion error.
     pub fn new(regions: Vec<MemoryRegion>) -> Memory {
-        Memory { regions }
+        assert!(regions.len() <= u16::MAX as usize, "Too many memory regions");
+        let page_count = regions.iter()
+            .map(|region| (region.end_address() + PAGE_SIZE - 1) >> PAGE_BITS)
+            .max()
+            .unwrap_or(0);
+        let mut pages = vec![Page::Unmapped; page_count];
+        for (index, region) in regions.iter().enumerate() {
+            if region.end_address() <= region.start_address {
+                continue;
+            }
+
+            let first_page = region.start_address >> PAGE_BITS;
+            let last_page = (region.end_address() - 1) >> PAGE_BITS;
+            for (page, page_index) in pages[first_page..=last_page].iter_mut().zip(first_page..) {
+                let page_start = page_index << PAGE_BITS;
+                let is_covered = region.start_address <= page_start &&
+                    page_start + PAGE_SIZE <= region.end_address();
+                // A page with more than 1 region in it needs to be searched
+                *page = match *page {
+                    Page::Unmapped if is_covered => Page::Region(index as u16),
+                    _ => Page::Split,
+                };
+            }
+        }
+
+        Memory { regions, pages }
     }
 
     pub fn read(&self, address: usize, buffer: &mut [u8]) -> MemoryResult<()> {
-        let region = self.find_region(address)?;
-        region.read(address, buffer)
+        let index = self.find_region(address)?;
+        self.regions[index].read(address, buffer)
     }
     pub fn write(&mut self, address: usize, buffer: &[u8]) -> MemoryResult<()> {
-        let region = self.find_region_mut(address)?;
-        region.write(address, buffer)
+        let index = self.find_region(address)?;
+        self.regions[index].write(address, buffer)
+    }
+
+    /// Gives the bytes behind the address directly, for fast (or bulk) reads.
+    /// Regions with a handler don't have any, and neither do accesses past the end of a region.
+    pub fn bytes(&self, address: usize, length: usize) -> Option<&[u8]> {
+        let index = self.find_region(address).ok()?;
+        self.regions[index].bytes(address, length)
+    }
+    /// Gives the bytes behind the address directly, for fast (or bulk) writes.
+    /// Read-only regions don't have any either.
+    pub fn bytes_mut(&mut self, address: usize, length: usize) -> Option<&mut [u8]> {
+        let index = self.find_region(address).ok()?;
+        self.regions[index].bytes_mut(address, length)
     }
 }
 impl Memory {
-    fn find_region(&self, address: usize) -> MemoryResult<&MemoryRegion> {
-        for region in &self.regions {
-            if region.start_address <= address && address <= region.end_address() {
-                return Ok(region);
-            }
+    /// Gives the index of the region that has the address
+    #[inline]
+    fn find_region(&self, address: usize) -> MemoryResult<usize> {
+        match self.pages.get(address >> PAGE_BITS) {
+            Some(Page::Region(index)) => Ok(*index as usize),
+            Some(Page::Split) => self.regions.iter()
+                .position(|region| {
+                    region.start_address <= address && address < region.end_address()
+                })
+                .ok_or(MemoryError::NoMatchingRegion),
+            Some(Page::Unmapped) | None => Err(MemoryError::NoMatchingRegion),
         }
-
-        Err(MemoryError::NoMatchingRegion)
     }
-    fn find_region_mut(&mut self, address: usize) -> MemoryResult<&mut MemoryRegion> {
-        for region in &mut self.regions {
-            if region.start_address <= address && address <= region.end_address() {
-                return Ok(region);
-            }
-        }
+}
 
-        Err(MemoryError::NoMatchingRegion)
-    }
+#[derive(Copy, Clone)]
+enum Page {
+    Unmapped,
+    /// The whole page belongs to the region at this index
+    Region(u16),
+    /// Part of the page belongs to a region, so the regions need to be searched
+    Split,
 }
 
-// TODO Mirrored regions
-#[derive(Eq, PartialEq)]
 pub struct MemoryRegion {
     start_address: usize,
-    bytes: Vec<u8>,
+    contents: RegionContents,
+    /// How often the region repeats, and how far the repeats go
+    mirror: Option<Mirror>,
+    writes: WritePermission,
 }
 impl MemoryRegion {
     pub fn new(start_address: usize, bytes: Vec<u8>) -> MemoryRegion {
-        MemoryRegion { start_address, bytes }
+        MemoryRegion::with_contents(start_address, RegionContents::Bytes(bytes))
+    }
+    /// Makes a read-only region over bytes that other regions can also use (like a ROM that shows
+    /// up at several addresses). Writes to it are ignored.
+    pub fn shared(start_address: usize, bytes: Rc<[u8]>) -> MemoryRegion {
+        MemoryRegion::with_contents(start_address, RegionContents::Shared(bytes))
+            .with_write_permission(WritePermission::Ignored)
+    }
+    /// Makes a region of the size where every access goes through the handler (like IO registers)
+    pub fn with_handler(start_address: usize, size: usize, handler: Box<dyn MemoryHandler>)
+    -> MemoryRegion {
+        MemoryRegion::with_contents(start_address, RegionContents::Handler { size, handler })
+    }
+    /// Makes a region of the size that nothing is connected to.
+    /// Reads give back `MemoryError::OpenBus` and writes are ignored.
+    pub fn open_bus(start_address: usize, size: usize) -> MemoryRegion {
+        MemoryRegion::with_contents(start_address, RegionContents::OpenBus { size })
+            .with_write_permission(WritePermission::Ignored)
+    }
+
+    /// Changes what happens to writes in the region. Shared regions can never be written.
+    pub fn with_write_permission(mut self, writes: WritePermission) -> MemoryRegion {
+        self.writes = writes;
+        self
+    }
+
+    /// Repeats the region every `mirror_size` bytes, until `span` bytes from the start.
+    /// If the mirror size is bigger than the region, the leftover space repeats the end of the
+    /// region again (like 96KB of VRAM in a 128KB mirror).
+    pub fn mirrored(mut self, mirror_size: usize, span: usize) -> MemoryRegion {
+        let size = self.size();
+        assert!(size <= mirror_size && mirror_size - size <= size,
+            "The region needs to fill its mirror");
+        self.mirror = Some(Mirror { size: mirror_size, span });
+        self
     }
 }
 impl MemoryRegion {
+    fn with_contents(start_address: usize, contents: RegionContents) -> MemoryRegion {
+        MemoryRegion {
+            start_address,
+            contents,
+            mirror: None,
+            writes: WritePermission::Allowed,
+        }
+    }
+
     #[inline]
-    fn end_address(&self) -> usize { self.start_address + self.bytes.len() }
+    fn end_address(&self) -> usize {
+        match &self.mirror {
+            Some(mirror) => self.start_address + mirror.span,
+            None => self.start_address + self.size(),
+        }
+    }
+
+    /// Where the address is in the region, after taking away any mirroring
+    fn offset_of(&self, address: usize) -> usize {
+        let offset = address - self.start_address;
+        match &self.mirror {
+            Some(mirror) => {
+                let offset = offset % mirror.size;
+                if offset < self.size() {
+                    offset
+                } else {
+                    offset - (mirror.size - self.size())
+                }
+            },
+            None => offset,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match &self.contents {
+            RegionContents::Bytes(bytes) => bytes.len(),
+            RegionContents::Shared(bytes) => bytes.len(),
+            RegionContents::Handler { size, .. } |
+            RegionContents::OpenBus { size } => *size,
+        }
+    }
+
+    /// Can only be called if the address is guaranteed to exist in here
+    fn bytes(&self, address: usize, length: usize) -> Option<&[u8]> {
+        let start_index = self.offset_of(address);
+        let end_length = start_index + length;
+        match &self.contents {
+            RegionContents::Bytes(bytes) => bytes.get(start_index..end_length),
+            RegionContents::Shared(bytes) => bytes.get(start_index..end_length),
+            RegionContents::Handler { .. } |
+            RegionContents::OpenBus { .. } => None,
+        }
+    }
+    /// Can only be called if the address is guaranteed to exist in here
+    fn bytes_mut(&mut self, address: usize, length: usize) -> Option<&mut [u8]> {
+        if self.writes != WritePermission::Allowed {
+            return None;
+        }
+
+        let start_index = self.offset_of(address);
+        let end_length = start_index + length;
+        match &mut self.contents {
+            RegionContents::Bytes(bytes) => bytes.get_mut(start_index..end_length),
+            RegionContents::Shared(_) |
+            RegionContents::Handler { .. } |
+            RegionContents::OpenBus { .. } => None,
+        }
+    }
 
     /// Can only be called if the address is guaranteed to exist in here
     fn read(&self, address: usize, buffer: &mut [u8]) -> MemoryResult<()> {
-        let start_index = address - self.start_address;
+        let start_index = self.offset_of(address);
         let end_length = start_index + buffer.len();
-        if end_length <= self.bytes.len() {
-            buffer.copy_from_slice(&self.bytes[start_index..end_length]);
-            Ok(())
-        } else {
-            Err(MemoryError::CrossRegionAccess)
+        if end_length > self.size() {
+            return Err(MemoryError::CrossRegionAccess);
         }
+
+        match &self.contents {
+            RegionContents::Bytes(bytes) =>
+                buffer.copy_from_slice(&bytes[start_index..end_length]),
+            RegionContents::Shared(bytes) =>
+                buffer.copy_from_slice(&bytes[start_index..end_length]),
+            RegionContents::Handler { handler, .. } => handler.read(start_index, buffer),
+            RegionContents::OpenBus { .. } => return Err(MemoryError::OpenBus),
+        }
+        Ok(())
     }
     /// Can only be called if the address is guaranteed to exist in here
     fn write(&mut self, address: usize, buffer: &[u8]) -> MemoryResult<()> {
-        let start_index = address - self.start_address;
+        match self.writes {
+            WritePermission::Allowed => {},
+            WritePermission::Ignored => return Ok(()),
+            WritePermission::Denied => return Err(MemoryError::ReadOnly),
+        }
+
+        let start_index = self.offset_of(address);
         let end_length = start_index + buffer.len();
-        if end_length <= self.bytes.len() {
-            self.bytes[start_index..end_length].copy_from_slice(buffer);
-            Ok(())
-        } else {
-            Err(MemoryError::CrossRegionAccess)
+        if end_length > self.size() {
+            return Err(MemoryError::CrossRegionAccess);
         }
+
+        match &mut self.contents {
+            RegionContents::Bytes(bytes) =>
+                bytes[start_index..end_length].copy_from_slice(buffer),
+            RegionContents::Shared(_) |
+            RegionContents::OpenBus { .. } => {},
+            RegionContents::Handler { handler, .. } => handler.write(start_index, buffer),
+        }
+        Ok(())
     }
 }
+
+/// What happens when something tries to write to a region
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WritePermission {
+    Allowed,
+    /// The write goes nowhere, like a write to ROM on most buses
+    Ignored,
+    /// The write fails with `MemoryError::ReadOnly`
+    Denied,
+}
+
+struct Mirror {
+    /// The region repeats every this many bytes
+    size: usize,
+    /// The number of bytes from the start of the region that the repeats cover
+    span: usize,
+}
+
+enum RegionContents {
+    /// Plain memory that just holds what gets written
+    Bytes(Vec<u8>),
+    /// Read-only memory that's shared with other regions
+    Shared(Rc<[u8]>),
+    /// Nothing is connected here
+    OpenBus {
+        size: usize,
+    },
+    /// Memory that can have side effects, so every access goes through the handler
+    Handler {
+        size: usize,
+        handler: Box<dyn MemoryHandler>,
+    },
+}
+
+/// Takes care of the accesses to a region, for anything that isn't plain memory.
+/// The offset is from the start of the region, and the access is guaranteed to fit in the region.
+pub trait MemoryHandler {
+    fn read(&self, offset: usize, buffer: &mut [u8]);
+    fn write(&mut self, offset: usize, buffer: &[u8]);
+}
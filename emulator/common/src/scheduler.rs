@@ -0,0 +1,61 @@
+/// Keeps the hardware events of a core in the order they happen.
+/// Times are absolute cycle timestamps, counted from when the scheduler was made.
+pub struct Scheduler<E> {
+    /// The current cycle
+    now: u64,
+    /// Sorted by time, where events at the same time keep the order they were scheduled in
+    events: Vec<(u64, E)>,
+}
+impl<E> Scheduler<E> {
+    pub fn new() -> Scheduler<E> {
+        Scheduler {
+            now: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn now(&self) -> u64 { self.now }
+
+    /// Moves time along by the cycles
+    pub fn advance(&mut self, cycles: usize) {
+        self.now += cycles as u64;
+    }
+
+    /// Schedules the event at the absolute time
+    pub fn schedule(&mut self, event: E, time: u64) {
+        let index = self.events.iter()
+            .position(|&(event_time, _)| event_time > time)
+            .unwrap_or(self.events.len());
+        self.events.insert(index, (time, event));
+    }
+    /// Schedules the event for the cycles after now
+    pub fn schedule_in(&mut self, event: E, cycles: usize) {
+        self.schedule(event, self.now + cycles as u64);
+    }
+
+    /// When the next event happens, if there is one
+    pub fn next_event_time(&self) -> Option<u64> {
+        self.events.first().map(|&(time, _)| time)
+    }
+
+    /// Takes the next event out if its time has come, along with the time it was scheduled at.
+    /// The time can be in the past when it was passed in the middle of something else.
+    pub fn pop_due(&mut self) -> Option<(E, u64)> {
+        match self.next_event_time() {
+            Some(time) if time <= self.now => {
+                let (time, event) = self.events.remove(0);
+                Some((event, time))
+            },
+            _ => None,
+        }
+    }
+}
+impl<E> Default for Scheduler<E> {
+    fn default() -> Scheduler<E> { Scheduler::new() }
+}
+impl<E: PartialEq> Scheduler<E> {
+    /// Takes out every pending copy of the event
+    pub fn cancel(&mut self, event: &E) {
+        self.events.retain(|(_, pending)| pending != event);
+    }
+}
@@ -0,0 +1,23 @@
+use brave_emulator_common::memory::MemoryResult;
+
+/// Little endian accesses that give back the cycles they took along with the data.
+/// The address is forced to be aligned for the width of the access.
+pub trait Bus {
+    fn read8(&mut self, address: u32, access_type: AccessType) -> MemoryResult<(u8, usize)>;
+    fn read16(&mut self, address: u32, access_type: AccessType) -> MemoryResult<(u16, usize)>;
+    fn read32(&mut self, address: u32, access_type: AccessType) -> MemoryResult<(u32, usize)>;
+
+    fn write8(&mut self, address: u32, value: u8, access_type: AccessType)
+    -> MemoryResult<usize>;
+    fn write16(&mut self, address: u32, value: u16, access_type: AccessType)
+    -> MemoryResult<usize>;
+    fn write32(&mut self, address: u32, value: u32, access_type: AccessType)
+    -> MemoryResult<usize>;
+}
+
+/// If an access carries on from the previous one (like instruction fetches usually do)
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum AccessType {
+    NonSequential,
+    Sequential,
+}
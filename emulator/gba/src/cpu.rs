@@ -1,156 +1,971 @@
-mod register;
-
-use brave_emulator_common::{
-    EmulatorCoreResult,
-    instruction_sets::{Arm32, Thumb32, Condition},
-    memory::{Memory, MemoryResult}
-};
-
-use crate::memory::{AccessWidth, GBAMemory};
-
-use self::register::RegisterSet;
-
-pub struct Cpu {
-    registers: RegisterSet,
-    fetcher: InstructionFetcher,
-    decoded: CpuInstruction,
-}
-impl Cpu {
-    pub fn new() -> Cpu {
-        Cpu {
-            registers: RegisterSet::default(),
-            fetcher: InstructionFetcher::default(),
-            decoded: CpuInstruction::None("Init".to_string()),
-        }
-    }
-
-    pub fn read_next_instruction(&mut self, memory: &mut GBAMemory) -> EmulatorCoreResult<usize> {
-        self.fetcher.read_next_instruction(memory, self.registers.r15 as usize,
-            self.registers.get_thumb_state())?;
-        let cycles = memory.get_cycles_for_address(self.registers.r15 as usize, AccessWidth::Bit32);
-        self.registers.r15 += 4;
-        Ok(cycles)
-    }
-
-    pub fn run_next_instruction(&mut self, memory: &mut GBAMemory) -> EmulatorCoreResult<usize> {
-        let mut cycles = 0;
-
-        // Run an instruction first if we have one
-        match self.decoded.take() {
-            // TODO Run the instruction
-            CpuInstruction::Arm(arm) => cycles += self.run_arm_instruction(memory, arm)?,
-            CpuInstruction::Thumb(thumb) => cycles += self.run_thumb_instruction(memory, thumb)?,
-            // Do nothing if none here since it could be init
-            CpuInstruction::None(e) => println!("Empty instruction, {:?}", e),
-        }
-
-        self.decode_instruction();
-        cycles += self.read_next_instruction(memory)?;
-        Ok(cycles)
-    }
-}
-impl Cpu {
-    fn decode_instruction(&mut self) {
-        self.decoded = self.fetcher.decode(self.registers.r15 as usize,
-            self.registers.get_thumb_state());
-        if let CpuInstruction::None(e) = &self.decoded {
-            panic!("At address {:#X}, {}", self.registers.r15, e.clone());
-        }
-    }
-
-    fn run_arm_instruction(&mut self, memory: &mut GBAMemory, arm: Arm32) ->
-    EmulatorCoreResult<usize> {
-        match arm {
-            Arm32::Branch(branch) => {
-                if branch.condition == Condition::AllSet {
-                    // Do the special BLX here
-                    self.registers.set_thumb_state(true);
-                    self.registers.r14 = self.registers.r15;
-                    self.registers.r15 = ((self.registers.r15 as i32) + branch.offset) as u32;
-                    if branch.opcode {
-                        // This wanted the other halfword
-                        self.registers.r15 += 2;
-                    }
-                    Ok(1 + self.read_next_instruction(memory)?)
-                } else if self.registers.does_condition_pass(branch.condition) {
-                    if branch.opcode {
-                        self.registers.r14 = self.registers.r15;
-                    }
-                    self.registers.r15 = ((self.registers.r15 as i32) + branch.offset) as u32;
-                    Ok(1 + self.read_next_instruction(memory)?)
-                } else {
-                    Ok(1)
-                }
-            }
-        }
-    }
-
-    fn run_thumb_instruction(&mut self, memory: &mut GBAMemory, thumb: Thumb32) ->
-    EmulatorCoreResult<usize> {
-        Ok(0)
-    }
-}
-
-#[derive(Default)]
-struct InstructionFetcher {
-    address_of_bytes: usize,
-    bytes: [u8; 4],
-}
-impl InstructionFetcher {
-    pub fn read_next_instruction(&mut self, memory: &Memory, mut address: usize, is_thumb: bool)
-    -> MemoryResult<()> {
-        if is_thumb &&
-            (self.address_of_bytes..self.address_of_bytes + 4).contains(&address) {
-            // Just use the back half if we haven't yet
-            return Ok(());
-        }
-
-        // Align the address to the word that's at or behind the address
-        address -= address % 4;
-        match memory.read(address, &mut self.bytes) {
-            Ok(_) => {
-                self.address_of_bytes = address;
-                Ok(())
-            },
-            Err(e) => Err(e)
-        }
-    }
-
-    pub fn decode(&self, address: usize, is_thumb: bool) -> CpuInstruction {
-        // TODO We need to raise an exception when the address is not aligned or inside our fetched instruction
-        if is_thumb {
-            let thumb_bytes: u16 = if address == self.address_of_bytes {
-                u16::from_le_bytes([self.bytes[0], self.bytes[1]])
-            } else {
-                u16::from_le_bytes([self.bytes[2], self.bytes[3]])
-            };
-            match Thumb32::find_instruction(thumb_bytes) {
-                Some(thumb_instruction) => CpuInstruction::Thumb(thumb_instruction),
-                None => CpuInstruction::None(
-                    format!("Failed to convert {:#X} ({:#b}) into Thumb32",
-                        thumb_bytes, thumb_bytes)
-                )
-            }
-        } else {
-            let arm_bytes= u32::from_le_bytes(self.bytes);
-            match Arm32::find_instruction(arm_bytes) {
-                Some(arm_instruction) => CpuInstruction::Arm(arm_instruction),
-                None => CpuInstruction::None(
-                    format!("Failed to convert {:#X} ({:#b}) into ARM32", arm_bytes, arm_bytes)
-                )
-            }
-        }
-    }
-}
-
-// Depending on which mode is currently set
-enum CpuInstruction {
-    Arm(Arm32),
-    Thumb(Thumb32),
-    None(String),
-}
-impl CpuInstruction {
-    pub fn take(&mut self) -> CpuInstruction {
-        std::mem::replace(self, CpuInstruction::None("Taken".to_string()))
-    }
-}
+mod alu;
+mod exception;
+mod pipeline;
+mod register;
+
+use brave_emulator_common::{
+    EmulatorCoreResult, EmulatorCoreError,
+    memory::MemoryError,
+    instruction_sets::{
+        ArmArchitecture, Arm32, Thumb32, Condition,
+        AluOpcode, DataProcessing, ShiftAmount, ShifterOperand,
+        SingleDataTransfer, TransferOffset,
+        HalfwordDataTransfer, HalfwordOffset, HalfwordTransferType,
+        BlockDataTransfer, Multiply, MultiplyLong,
+        StatusToRegister, RegisterToStatus, StatusOperand,
+        BranchExchange, Swap, SoftwareInterrupt, Shift, ShiftType,
+        AluOperation, ImmediateOpcode, ThumbAluOpcode, HiRegisterOpcode, SignExtendedType,
+    },
+};
+
+use crate::{
+    bus::{AccessType, Bus},
+    memory::GBAMemory,
+};
+
+use self::{
+    exception::Exception,
+    pipeline::{CpuInstruction, Pipeline},
+    register::RegisterSet,
+};
+
+/// The ARM7TDMI in the GBA
+const ARCHITECTURE: ArmArchitecture = ArmArchitecture::V4T;
+
+pub struct Cpu {
+    registers: RegisterSet,
+    pipeline: Pipeline,
+    /// If the IRQ line is being held by an interrupt source
+    irq_line: bool,
+    /// If the FIQ line is being held by an interrupt source
+    fiq_line: bool,
+    /// The cycles that the running instruction had already spent when one of its accesses failed
+    aborted_cycles: usize,
+}
+impl Cpu {
+    pub fn new() -> Cpu {
+        Cpu {
+            registers: RegisterSet::new(),
+            pipeline: Pipeline::new(),
+            irq_line: false,
+            fiq_line: false,
+            aborted_cycles: 0,
+        }
+    }
+
+    /// Takes the reset exception and fills the pipeline from the reset vector.
+    /// Gives back the cycles that took.
+    pub fn reset(&mut self, memory: &mut GBAMemory) -> usize {
+        self.enter_exception(Exception::Reset, 0);
+        self.fill_pipeline(memory)
+    }
+
+    pub fn set_irq_line(&mut self, asserted: bool) { self.irq_line = asserted; }
+    // Nothing on the GBA is wired to the FIQ line, but the ARM7TDMI still has it
+    #[allow(dead_code)]
+    pub fn set_fiq_line(&mut self, asserted: bool) { self.fiq_line = asserted; }
+
+    pub fn run_next_instruction(&mut self, memory: &mut GBAMemory) -> EmulatorCoreResult<usize> {
+        let mut cycles = 0;
+        if self.pipeline.is_empty() {
+            cycles += self.fill_pipeline(memory);
+        }
+
+        // Where the next fetch was going to be, in case the instruction flushes the pipeline
+        let fetch_address = self.registers.r15;
+        let was_thumb = self.registers.get_thumb_state();
+
+        // Interrupts are only taken between instructions.
+        // The instruction that was going to run next is where the handler returns to.
+        if let Some(interrupt) = self.pending_interrupt() {
+            let address = self.pipeline.decoded_address().expect("Programming error");
+            self.enter_exception(interrupt, address);
+            return Ok(cycles + self.refill_pipeline(memory, fetch_address, was_thumb));
+        }
+
+        let (address, instruction) = self.pipeline.take_decoded().expect("Programming error");
+        if instruction.accesses_memory() {
+            self.pipeline.interrupt_sequence();
+        }
+        self.aborted_cycles = 0;
+        let result = match instruction {
+            CpuInstruction::Arm(arm) => self.run_arm_instruction(memory, arm),
+            CpuInstruction::Thumb(thumb) => self.run_thumb_instruction(memory, thumb),
+            CpuInstruction::Undecodable => {
+                self.enter_exception(Exception::Undefined, address);
+                Ok(0)
+            },
+            CpuInstruction::PrefetchAbort => {
+                self.enter_exception(Exception::PrefetchAbort, address);
+                Ok(0)
+            },
+        };
+        match result {
+            Ok(ran_cycles) => {
+                // The gamepak bus is free while the instruction executes (unless it used it)
+                memory.run_prefetch(ran_cycles);
+                cycles += ran_cycles;
+            },
+            // The load or store failed, so the guest gets to handle it.
+            // The accesses before the one that failed still took their time.
+            Err(EmulatorCoreError::MemoryError(_)) => {
+                memory.run_prefetch(self.aborted_cycles);
+                cycles += self.aborted_cycles;
+                self.enter_exception(Exception::DataAbort, address);
+            },
+            Err(e) => return Err(e),
+        }
+
+        // A branch (or any write to the PC) flushes the pipeline, so it needs a refill
+        if self.pipeline.is_empty() {
+            cycles += self.refill_pipeline(memory, fetch_address, was_thumb);
+        } else {
+            let is_thumb = self.registers.get_thumb_state();
+            self.pipeline.decode(is_thumb);
+            cycles += self.pipeline.fetch(memory, &mut self.registers.r15, is_thumb);
+        }
+        Ok(cycles)
+    }
+}
+impl Cpu {
+    /// Fills the pipeline from the PC, giving back the cycles that took.
+    /// The 1st fetch is non-sequential and the 2nd one is sequential.
+    fn fill_pipeline(&mut self, memory: &mut GBAMemory) -> usize {
+        let is_thumb = self.registers.get_thumb_state();
+        self.pipeline.flush();
+        let mut cycles = self.pipeline.fetch(memory, &mut self.registers.r15, is_thumb);
+        self.pipeline.decode(is_thumb);
+        cycles += self.pipeline.fetch(memory, &mut self.registers.r15, is_thumb);
+        cycles
+    }
+
+    /// Refills the pipeline after a branch, giving back the cycles that took.
+    /// The fetch from the old PC was already under way, so it still costs a sequential access.
+    fn refill_pipeline(&mut self, memory: &mut GBAMemory, discarded_address: u32,
+    was_thumb: bool) -> usize {
+        memory.discarded_fetch_cycles(discarded_address, was_thumb) + self.fill_pipeline(memory)
+    }
+
+    /// The interrupt that should be taken now, with FIQs having the higher priority
+    fn pending_interrupt(&self) -> Option<Exception> {
+        if self.fiq_line && !self.registers.is_fiq_disabled() {
+            Some(Exception::Fiq)
+        } else if self.irq_line && !self.registers.is_irq_disabled() {
+            Some(Exception::Irq)
+        } else {
+            None
+        }
+    }
+
+    /// Switches to the mode for the exception and jumps to its vector in the BIOS.
+    /// The address is of the instruction that caused it (or the next one for an interrupt).
+    fn enter_exception(&mut self, exception: Exception, address: u32) {
+        let cpsr = self.registers.get_cpsr();
+        let return_address = exception.return_address(address, self.registers.get_thumb_state());
+        self.registers.set_mode(exception.mode());
+        self.registers.set_spsr(cpsr);
+        self.registers.r14 = return_address;
+        // Every handler starts in ARM state
+        self.registers.set_thumb_state(false);
+        self.registers.set_irq_disabled(true);
+        if exception.disables_fiq() {
+            self.registers.set_fiq_disabled(true);
+        }
+        self.jump_to(exception.vector());
+    }
+
+    /// The executors only give back the cycles for their data accesses and internal cycles.
+    /// Fetching the next opcode (or refilling after a branch) is counted by the pipeline.
+    fn run_arm_instruction(&mut self, memory: &mut GBAMemory, arm: Arm32) ->
+    EmulatorCoreResult<usize> {
+        match arm {
+            // BLX (the full condition) doesn't exist for our architecture
+            Arm32::Branch(branch) => {
+                if self.registers.does_condition_pass(branch.condition) {
+                    if branch.opcode {
+                        // Return to the instruction after this one
+                        self.registers.r14 = self.registers.r15 - 4;
+                    }
+                    self.jump_to(self.registers.r15.wrapping_add(branch.offset as u32));
+                }
+                Ok(0)
+            },
+            Arm32::DataProcessing(data_processing) => self.run_data_processing(data_processing),
+            Arm32::SingleDataTransfer(transfer) =>
+                self.run_single_data_transfer(memory, transfer),
+            Arm32::HalfwordDataTransfer(transfer) =>
+                self.run_halfword_data_transfer(memory, transfer),
+            Arm32::BlockDataTransfer(transfer) =>
+                self.run_block_data_transfer(memory, transfer),
+            Arm32::Multiply(multiply) => Ok(self.run_multiply(multiply)),
+            Arm32::MultiplyLong(multiply) => Ok(self.run_multiply_long(multiply)),
+            Arm32::StatusToRegister(transfer) => Ok(self.run_status_to_register(transfer)),
+            Arm32::RegisterToStatus(transfer) => Ok(self.run_register_to_status(transfer)),
+            Arm32::BranchExchange(branch) => self.run_branch_exchange(branch),
+            Arm32::Swap(swap) => self.run_swap(memory, swap),
+            Arm32::SoftwareInterrupt(interrupt) => self.run_software_interrupt(interrupt),
+            // These are never decoded for our architecture
+            Arm32::CountLeadingZeros(_) |
+            Arm32::SaturatingArithmetic(_) |
+            Arm32::SignedHalfwordMultiply(_) => panic!("Programming error"),
+        }
+    }
+
+    fn run_data_processing(&mut self, instruction: DataProcessing) -> EmulatorCoreResult<usize> {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return Ok(0);
+        }
+
+        let mut cycles = 0;
+        let carry = self.registers.get_carry_flag();
+        // The PC is another word ahead when the shift amount comes from a register
+        let mut pc_offset = 0;
+        let (operand2, shifter_carry) = match instruction.operand2 {
+            ShifterOperand::Immediate { value, rotate } => {
+                let value = (value as u32).rotate_right(rotate as u32);
+                // The carry is only touched when there was a rotate
+                let shifter_carry = if rotate == 0 { carry } else { value >> 31 == 1 };
+                (value, shifter_carry)
+            },
+            ShifterOperand::Register { rm, shift } => {
+                let amount = match shift.amount {
+                    ShiftAmount::Immediate(amount) => amount as u32,
+                    ShiftAmount::Register(rs) => {
+                        // Reading the shift register costs an internal cycle
+                        cycles += 1;
+                        pc_offset = 4;
+                        self.registers.get(rs) & 0xFF
+                    },
+                };
+                let value = self.read_operand_register(rm, pc_offset);
+                alu::shift(shift.shift_type, value, amount, carry)
+            },
+        };
+        let operand1 = self.read_operand_register(instruction.rn, pc_offset);
+
+        let (result, carry, overflow) = match instruction.opcode {
+            AluOpcode::And | AluOpcode::Test => (operand1 & operand2, shifter_carry, None),
+            AluOpcode::ExclusiveOr | AluOpcode::TestEqual =>
+                (operand1 ^ operand2, shifter_carry, None),
+            AluOpcode::Or => (operand1 | operand2, shifter_carry, None),
+            AluOpcode::Move => (operand2, shifter_carry, None),
+            AluOpcode::BitClear => (operand1 & !operand2, shifter_carry, None),
+            AluOpcode::MoveNot => (!operand2, shifter_carry, None),
+            arithmetic => {
+                let (a, b, carry_in) = match arithmetic {
+                    AluOpcode::Subtract | AluOpcode::Compare => (operand1, !operand2, true),
+                    AluOpcode::ReverseSubtract => (operand2, !operand1, true),
+                    AluOpcode::Add | AluOpcode::CompareNegative => (operand1, operand2, false),
+                    AluOpcode::AddCarry => (operand1, operand2, carry),
+                    AluOpcode::SubtractCarry => (operand1, !operand2, carry),
+                    AluOpcode::ReverseSubtractCarry => (operand2, !operand1, carry),
+                    _ => panic!("Programming error"),
+                };
+                let (result, carry, overflow) = alu::add_with_carry(a, b, carry_in);
+                (result, carry, Some(overflow))
+            },
+        };
+
+        if instruction.set_condition_codes {
+            if instruction.rd == 15 && !instruction.opcode.is_test() {
+                // This is how exception handlers return
+                self.registers.restore_cpsr();
+            } else {
+                self.registers.set_result_flags(result);
+                self.registers.set_carry_flag(carry);
+                if let Some(overflow) = overflow {
+                    self.registers.set_overflow_flag(overflow);
+                }
+            }
+        }
+
+        if !instruction.opcode.is_test() {
+            if instruction.rd == 15 {
+                self.jump_to(result);
+            } else {
+                self.registers.set(instruction.rd, result);
+            }
+        }
+        Ok(cycles)
+    }
+
+    fn run_single_data_transfer(&mut self, memory: &mut GBAMemory, instruction: SingleDataTransfer)
+    -> EmulatorCoreResult<usize> {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return Ok(0);
+        }
+
+        let offset = match instruction.offset {
+            TransferOffset::Immediate(offset) => offset as u32,
+            TransferOffset::Register { rm, shift } => {
+                let amount = match shift.amount {
+                    ShiftAmount::Immediate(amount) => amount as u32,
+                    ShiftAmount::Register(_) => panic!("Programming error"),
+                };
+                let carry = self.registers.get_carry_flag();
+                alu::shift(shift.shift_type, self.registers.get(rm), amount, carry).0
+            },
+        };
+        let base = self.registers.get(instruction.rn);
+        let offset_address = if instruction.up {
+            base.wrapping_add(offset)
+        } else {
+            base.wrapping_sub(offset)
+        };
+        let address = if instruction.pre_index { offset_address } else { base };
+        // Post-indexing always writes back
+        let write_back = !instruction.pre_index || instruction.write_back;
+
+        let mut cycles = 0;
+        if instruction.load {
+            let (value, access_cycles) = if instruction.byte {
+                let (value, access_cycles) = memory.read8(address, AccessType::NonSequential)?;
+                (value as u32, access_cycles)
+            } else {
+                // Unaligned words are rotated so that the addressed byte ends up at the bottom
+                let (value, access_cycles) = memory.read32(address, AccessType::NonSequential)?;
+                (value.rotate_right((address & 0b11) * 8), access_cycles)
+            };
+            // The loaded value needs an internal cycle to get into the register
+            cycles += access_cycles + 1;
+
+            // The loaded value wins if the base is also the destination
+            if write_back {
+                self.registers.set(instruction.rn, offset_address);
+            }
+            if instruction.rd == 15 {
+                self.jump_to(value);
+            } else {
+                self.registers.set(instruction.rd, value);
+            }
+        } else {
+            // Storing the PC gives the address of this instruction plus 12
+            let value = self.read_operand_register(instruction.rd, 4);
+            cycles += if instruction.byte {
+                memory.write8(address, value as u8, AccessType::NonSequential)?
+            } else {
+                memory.write32(address, value, AccessType::NonSequential)?
+            };
+
+            if write_back {
+                self.registers.set(instruction.rn, offset_address);
+            }
+        }
+        Ok(cycles)
+    }
+
+    fn run_halfword_data_transfer(&mut self, memory: &mut GBAMemory,
+    instruction: HalfwordDataTransfer) -> EmulatorCoreResult<usize> {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return Ok(0);
+        }
+
+        let offset = match instruction.offset {
+            HalfwordOffset::Immediate(offset) => offset as u32,
+            HalfwordOffset::Register(rm) => self.registers.get(rm),
+        };
+        let base = self.registers.get(instruction.rn);
+        let offset_address = if instruction.up {
+            base.wrapping_add(offset)
+        } else {
+            base.wrapping_sub(offset)
+        };
+        let address = if instruction.pre_index { offset_address } else { base };
+        // Post-indexing always writes back
+        let write_back = !instruction.pre_index || instruction.write_back;
+
+        let mut cycles = 0;
+        if instruction.load {
+            let (value, access_cycles) = match instruction.transfer_type {
+                HalfwordTransferType::Halfword => {
+                    // Unaligned halfwords are rotated just like words
+                    let (value, access_cycles) =
+                        memory.read16(address, AccessType::NonSequential)?;
+                    ((value as u32).rotate_right((address & 1) * 8), access_cycles)
+                },
+                HalfwordTransferType::SignedHalfword if address & 1 == 0 => {
+                    let (value, access_cycles) =
+                        memory.read16(address, AccessType::NonSequential)?;
+                    (value as i16 as u32, access_cycles)
+                },
+                // A signed halfword load from an odd address only reads the byte
+                _ => {
+                    let (value, access_cycles) = memory.read8(address, AccessType::NonSequential)?;
+                    (value as i8 as u32, access_cycles)
+                },
+            };
+            // The loaded value needs an internal cycle to get into the register
+            cycles += access_cycles + 1;
+
+            // The loaded value wins if the base is also the destination
+            if write_back {
+                self.registers.set(instruction.rn, offset_address);
+            }
+            if instruction.rd == 15 {
+                self.jump_to(value);
+            } else {
+                self.registers.set(instruction.rd, value);
+            }
+        } else {
+            // Storing the PC gives the address of this instruction plus 12
+            let value = self.read_operand_register(instruction.rd, 4);
+            cycles += memory.write16(address, value as u16, AccessType::NonSequential)?;
+
+            if write_back {
+                self.registers.set(instruction.rn, offset_address);
+            }
+        }
+        Ok(cycles)
+    }
+
+    fn run_block_data_transfer(&mut self, memory: &mut GBAMemory, instruction: BlockDataTransfer)
+    -> EmulatorCoreResult<usize> {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return Ok(0);
+        }
+
+        // An empty list transfers R15 but moves the base as if all 16 registers were used
+        let (register_list, count) = match instruction.register_list {
+            0 => (1 << 15, 16),
+            register_list => (register_list, register_list.count_ones()),
+        };
+        let base = self.registers.get(instruction.rn);
+        let (mut address, final_address) = if instruction.up {
+            (base, base.wrapping_add(count * 4))
+        } else {
+            let lowest_address = base.wrapping_sub(count * 4);
+            (lowest_address, lowest_address)
+        };
+        // The transfers always go from the lowest address up, so the down modes flip the pre-index
+        if instruction.pre_index == instruction.up {
+            address = address.wrapping_add(4);
+        }
+        let has_pc = register_list & (1 << 15) != 0;
+        let user_bank = instruction.psr_or_user && !(instruction.load && has_pc);
+
+        let mut cycles = 0;
+        // Only the first transfer is non-sequential
+        let mut access_type = AccessType::NonSequential;
+        if instruction.load {
+            // Writing back first lets a loaded base win
+            if instruction.write_back {
+                self.registers.set(instruction.rn, final_address);
+            }
+
+            let mut new_pc = None;
+            for register in (0..16).filter(|register| register_list & (1 << register) != 0) {
+                let (value, access_cycles) = memory.read32(address, access_type)
+                    .map_err(|error| self.abort_after(cycles, error))?;
+                cycles += access_cycles;
+                access_type = AccessType::Sequential;
+                if register == 15 {
+                    new_pc = Some(value);
+                } else if user_bank {
+                    self.registers.set_user_bank(register, value);
+                } else {
+                    self.registers.set(register, value);
+                }
+                address = address.wrapping_add(4);
+            }
+            // The last loaded value needs an internal cycle to get into the register
+            cycles += 1;
+
+            if let Some(new_pc) = new_pc {
+                if instruction.psr_or_user {
+                    self.registers.restore_cpsr();
+                }
+                self.jump_to(new_pc);
+            }
+        } else {
+            let first_register = register_list.trailing_zeros() as u8;
+            for register in (0..16).filter(|register| register_list & (1 << register) != 0) {
+                let value = if register == 15 {
+                    // Storing the PC gives the address of this instruction plus 12
+                    self.read_operand_register(register, 4)
+                } else if register == instruction.rn && instruction.write_back &&
+                    register != first_register {
+                    // The base has already been written back unless it was stored first
+                    final_address
+                } else if user_bank {
+                    self.registers.get_user_bank(register)
+                } else {
+                    self.registers.get(register)
+                };
+                cycles += memory.write32(address, value, access_type)
+                    .map_err(|error| self.abort_after(cycles, error))?;
+                access_type = AccessType::Sequential;
+                address = address.wrapping_add(4);
+            }
+
+            if instruction.write_back {
+                self.registers.set(instruction.rn, final_address);
+            }
+        }
+        Ok(cycles)
+    }
+
+    fn run_multiply(&mut self, instruction: Multiply) -> usize {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return 0;
+        }
+
+        let multiplier = self.registers.get(instruction.rs);
+        let mut cycles = alu::multiply_cycles(multiplier, true);
+        let mut result = self.registers.get(instruction.rm).wrapping_mul(multiplier);
+        if instruction.accumulate {
+            result = result.wrapping_add(self.registers.get(instruction.rn));
+            cycles += 1;
+        }
+
+        if instruction.set_condition_codes {
+            // The carry is left alone since it's meaningless after a multiply
+            self.registers.set_result_flags(result);
+        }
+        self.registers.set(instruction.rd, result);
+        cycles
+    }
+
+    fn run_multiply_long(&mut self, instruction: MultiplyLong) -> usize {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return 0;
+        }
+
+        let multiplier = self.registers.get(instruction.rs);
+        let multiplicand = self.registers.get(instruction.rm);
+        // Long multiplies always take an extra internal cycle
+        let mut cycles = 1 + alu::multiply_cycles(multiplier, instruction.signed);
+        let mut result = if instruction.signed {
+            (multiplicand as i32 as i64).wrapping_mul(multiplier as i32 as i64) as u64
+        } else {
+            multiplicand as u64 * multiplier as u64
+        };
+        if instruction.accumulate {
+            let accumulator = ((self.registers.get(instruction.rd_hi) as u64) << 32) |
+                self.registers.get(instruction.rd_lo) as u64;
+            result = result.wrapping_add(accumulator);
+            cycles += 1;
+        }
+
+        if instruction.set_condition_codes {
+            // Only the N and Z flags make sense, using all 64 bits
+            self.registers.set_result_flags((result >> 32) as u32);
+            self.registers.set_zero_flag(result == 0);
+        }
+        self.registers.set(instruction.rd_lo, result as u32);
+        self.registers.set(instruction.rd_hi, (result >> 32) as u32);
+        cycles
+    }
+
+    fn run_status_to_register(&mut self, instruction: StatusToRegister) -> usize {
+        if self.registers.does_condition_pass(instruction.condition) {
+            let value = if instruction.spsr {
+                self.registers.get_spsr()
+            } else {
+                self.registers.get_cpsr()
+            };
+            self.registers.set(instruction.rd, value);
+        }
+        0
+    }
+
+    fn run_register_to_status(&mut self, instruction: RegisterToStatus) -> usize {
+        if self.registers.does_condition_pass(instruction.condition) {
+            let value = match instruction.operand {
+                StatusOperand::Immediate { value, rotate } =>
+                    (value as u32).rotate_right(rotate as u32),
+                StatusOperand::Register(rm) => self.registers.get(rm),
+            };
+            self.registers.write_psr_fields(instruction.spsr, value, instruction.field_mask);
+        }
+        0
+    }
+
+    fn run_branch_exchange(&mut self, instruction: BranchExchange) -> EmulatorCoreResult<usize> {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return Ok(0);
+        }
+
+        let address = self.registers.get(instruction.rn);
+        self.registers.set_thumb_state(address & 1 == 1);
+        self.jump_to(address);
+        Ok(0)
+    }
+
+    fn run_swap(&mut self, memory: &mut GBAMemory, instruction: Swap) -> EmulatorCoreResult<usize> {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return Ok(0);
+        }
+
+        let address = self.registers.get(instruction.rn);
+        let source = self.registers.get(instruction.rm);
+        // The read and the write both go to the same address
+        let (value, access_cycles) = if instruction.byte {
+            let (value, read_cycles) = memory.read8(address, AccessType::NonSequential)?;
+            let write_cycles = memory.write8(address, source as u8, AccessType::NonSequential)
+                .map_err(|error| self.abort_after(read_cycles, error))?;
+            (value as u32, read_cycles + write_cycles)
+        } else {
+            // Unaligned words are rotated just like a LDR
+            let (value, read_cycles) = memory.read32(address, AccessType::NonSequential)?;
+            let write_cycles = memory.write32(address, source, AccessType::NonSequential)
+                .map_err(|error| self.abort_after(read_cycles, error))?;
+            (value.rotate_right((address & 0b11) * 8), read_cycles + write_cycles)
+        };
+        self.registers.set(instruction.rd, value);
+        // Plus an internal cycle
+        Ok(1 + access_cycles)
+    }
+
+    fn run_software_interrupt(&mut self, instruction: SoftwareInterrupt)
+    -> EmulatorCoreResult<usize> {
+        if !self.registers.does_condition_pass(instruction.condition) {
+            return Ok(0);
+        }
+
+        // The PC is 2 instructions ahead of this one
+        let address = if self.registers.get_thumb_state() {
+            self.registers.r15.wrapping_sub(4)
+        } else {
+            self.registers.r15.wrapping_sub(8)
+        };
+        self.enter_exception(Exception::SoftwareInterrupt, address);
+        Ok(0)
+    }
+
+    /// Keeps the cycles spent before a failed access, which the data abort still has to charge.
+    /// Only needed once an instruction has done an access that worked.
+    fn abort_after(&mut self, cycles: usize, error: MemoryError) -> MemoryError {
+        self.aborted_cycles = cycles;
+        error
+    }
+
+    /// Moves the PC to the (aligned) address and flushes the pipeline.
+    /// The refill happens after the instruction is done.
+    fn jump_to(&mut self, address: u32) {
+        self.registers.r15 = if self.registers.get_thumb_state() {
+            address & !1
+        } else {
+            address & !0b11
+        };
+        self.pipeline.flush();
+    }
+
+    /// Reads a register for an operand, with the extra offset applied if it's the PC
+    fn read_operand_register(&self, register: u8, pc_offset: u32) -> u32 {
+        if register == 15 {
+            self.registers.r15 + pc_offset
+        } else {
+            self.registers.get(register)
+        }
+    }
+
+    /// Most Thumb instructions are ARM instructions with a shorter encoding,
+    /// so those get run as their ARM equivalent (which also has the same timing).
+    fn run_thumb_instruction(&mut self, memory: &mut GBAMemory, thumb: Thumb32) ->
+    EmulatorCoreResult<usize> {
+        match thumb {
+            Thumb32::MoveShiftedRegister(shift) => self.run_data_processing(DataProcessing {
+                condition: Condition::Always,
+                opcode: AluOpcode::Move,
+                set_condition_codes: true,
+                rn: 0,
+                rd: shift.rd,
+                operand2: ShifterOperand::Register { rm: shift.rs, shift: shift.shift },
+            }),
+            Thumb32::AddSubtract(add) => {
+                let operand2 = if add.immediate {
+                    ShifterOperand::Immediate { value: add.operand, rotate: 0 }
+                } else {
+                    thumb_register_operand(add.operand)
+                };
+                self.run_data_processing(DataProcessing {
+                    condition: Condition::Always,
+                    opcode: if add.subtract { AluOpcode::Subtract } else { AluOpcode::Add },
+                    set_condition_codes: true,
+                    rn: add.rs,
+                    rd: add.rd,
+                    operand2,
+                })
+            },
+            Thumb32::ImmediateOperation(operation) => {
+                let opcode = match operation.opcode {
+                    ImmediateOpcode::Move => AluOpcode::Move,
+                    ImmediateOpcode::Compare => AluOpcode::Compare,
+                    ImmediateOpcode::Add => AluOpcode::Add,
+                    ImmediateOpcode::Subtract => AluOpcode::Subtract,
+                };
+                self.run_data_processing(DataProcessing {
+                    condition: Condition::Always,
+                    opcode,
+                    set_condition_codes: true,
+                    rn: operation.rd,
+                    rd: operation.rd,
+                    operand2: ShifterOperand::Immediate { value: operation.offset, rotate: 0 },
+                })
+            },
+            Thumb32::AluOperation(operation) => self.run_thumb_alu_operation(operation),
+            Thumb32::HiRegisterOperation(operation) => {
+                let opcode = match operation.opcode {
+                    HiRegisterOpcode::Add => AluOpcode::Add,
+                    HiRegisterOpcode::Compare => AluOpcode::Compare,
+                    HiRegisterOpcode::Move => AluOpcode::Move,
+                    HiRegisterOpcode::BranchExchange =>
+                        return self.run_branch_exchange(BranchExchange {
+                            condition: Condition::Always,
+                            link: false,
+                            rn: operation.rs,
+                        }),
+                    // This is never decoded for our architecture
+                    HiRegisterOpcode::BranchLinkExchange => panic!("Programming error"),
+                };
+                self.run_data_processing(DataProcessing {
+                    condition: Condition::Always,
+                    opcode,
+                    // Only the compare touches the flags
+                    set_condition_codes: opcode == AluOpcode::Compare,
+                    rn: operation.rd,
+                    rd: operation.rd,
+                    operand2: thumb_register_operand(operation.rs),
+                })
+            },
+            Thumb32::PcRelativeLoad(load) => {
+                // The PC has bit 1 forced off so that the address is always word aligned
+                let address = (self.registers.r15 & !0b11) + load.offset as u32;
+                let (value, cycles) = memory.read32(address, AccessType::NonSequential)?;
+                self.registers.set(load.rd, value);
+                // Plus an internal cycle
+                Ok(1 + cycles)
+            },
+            Thumb32::LoadStoreRegisterOffset(transfer) =>
+                self.run_single_data_transfer(memory, SingleDataTransfer {
+                    condition: Condition::Always,
+                    pre_index: true,
+                    up: true,
+                    byte: transfer.byte,
+                    write_back: false,
+                    load: transfer.load,
+                    rn: transfer.rb,
+                    rd: transfer.rd,
+                    offset: TransferOffset::Register {
+                        rm: transfer.ro,
+                        shift: Shift {
+                            shift_type: ShiftType::LogicalLeft,
+                            amount: ShiftAmount::Immediate(0),
+                        },
+                    },
+                }),
+            Thumb32::LoadStoreSignExtended(transfer) => {
+                let (load, transfer_type) = match transfer.transfer_type {
+                    SignExtendedType::StoreHalfword => (false, HalfwordTransferType::Halfword),
+                    SignExtendedType::LoadSignedByte => (true, HalfwordTransferType::SignedByte),
+                    SignExtendedType::LoadHalfword => (true, HalfwordTransferType::Halfword),
+                    SignExtendedType::LoadSignedHalfword =>
+                        (true, HalfwordTransferType::SignedHalfword),
+                };
+                self.run_halfword_data_transfer(memory, HalfwordDataTransfer {
+                    condition: Condition::Always,
+                    pre_index: true,
+                    up: true,
+                    write_back: false,
+                    load,
+                    rn: transfer.rb,
+                    rd: transfer.rd,
+                    transfer_type,
+                    offset: HalfwordOffset::Register(transfer.ro),
+                })
+            },
+            Thumb32::LoadStoreImmediateOffset(transfer) =>
+                self.run_single_data_transfer(memory, SingleDataTransfer {
+                    condition: Condition::Always,
+                    pre_index: true,
+                    up: true,
+                    byte: transfer.byte,
+                    write_back: false,
+                    load: transfer.load,
+                    rn: transfer.rb,
+                    rd: transfer.rd,
+                    offset: TransferOffset::Immediate(transfer.offset as u16),
+                }),
+            Thumb32::LoadStoreHalfword(transfer) =>
+                self.run_halfword_data_transfer(memory, HalfwordDataTransfer {
+                    condition: Condition::Always,
+                    pre_index: true,
+                    up: true,
+                    write_back: false,
+                    load: transfer.load,
+                    rn: transfer.rb,
+                    rd: transfer.rd,
+                    transfer_type: HalfwordTransferType::Halfword,
+                    offset: HalfwordOffset::Immediate(transfer.offset),
+                }),
+            Thumb32::SpRelativeLoadStore(transfer) =>
+                self.run_single_data_transfer(memory, SingleDataTransfer {
+                    condition: Condition::Always,
+                    pre_index: true,
+                    up: true,
+                    byte: false,
+                    write_back: false,
+                    load: transfer.load,
+                    rn: 13,
+                    rd: transfer.rd,
+                    offset: TransferOffset::Immediate(transfer.offset),
+                }),
+            Thumb32::LoadAddress(load) => {
+                let base = if load.sp {
+                    self.registers.r13
+                } else {
+                    // The PC has bit 1 forced off
+                    self.registers.r15 & !0b11
+                };
+                self.registers.set(load.rd, base.wrapping_add(load.offset as u32));
+                Ok(0)
+            },
+            Thumb32::AddOffsetToSp(add) => {
+                self.registers.r13 = self.registers.r13.wrapping_add(add.offset as i32 as u32);
+                Ok(0)
+            },
+            Thumb32::PushPop(transfer) => {
+                let mut register_list = transfer.register_list as u16;
+                if transfer.pc_or_lr {
+                    register_list |= if transfer.load { 1 << 15 } else { 1 << 14 };
+                }
+                // PUSH is STMDB SP! and POP is LDMIA SP!
+                self.run_block_data_transfer(memory, BlockDataTransfer {
+                    condition: Condition::Always,
+                    pre_index: !transfer.load,
+                    up: transfer.load,
+                    psr_or_user: false,
+                    write_back: true,
+                    load: transfer.load,
+                    rn: 13,
+                    register_list,
+                })
+            },
+            Thumb32::MultipleLoadStore(transfer) =>
+                self.run_block_data_transfer(memory, BlockDataTransfer {
+                    condition: Condition::Always,
+                    pre_index: false,
+                    up: true,
+                    psr_or_user: false,
+                    write_back: true,
+                    load: transfer.load,
+                    rn: transfer.rb,
+                    register_list: transfer.register_list as u16,
+                }),
+            Thumb32::ConditionalBranch(branch) => {
+                if self.registers.does_condition_pass(branch.condition) {
+                    let address = self.registers.r15.wrapping_add(branch.offset as i32 as u32);
+                    self.jump_to(address);
+                }
+                Ok(0)
+            },
+            Thumb32::SoftwareInterrupt(interrupt) =>
+                self.run_software_interrupt(SoftwareInterrupt {
+                    condition: Condition::Always,
+                    comment: interrupt.comment as u32,
+                }),
+            Thumb32::UnconditionalBranch(branch) => {
+                let address = self.registers.r15.wrapping_add(branch.offset as i32 as u32);
+                self.jump_to(address);
+                Ok(0)
+            },
+            Thumb32::LongBranchWithLink(branch) => {
+                if branch.low {
+                    // Return to the instruction after this one, staying in Thumb
+                    let return_address = (self.registers.r15 - 2) | 1;
+                    let address = self.registers.r14.wrapping_add((branch.offset as u32) << 1);
+                    self.registers.r14 = return_address;
+                    self.jump_to(address);
+                    Ok(0)
+                } else {
+                    // Sign extend the upper part of the offset
+                    let offset = (((branch.offset as u32) << 21) as i32 >> 9) as u32;
+                    self.registers.r14 = self.registers.r15.wrapping_add(offset);
+                    Ok(0)
+                }
+            },
+        }
+    }
+
+    fn run_thumb_alu_operation(&mut self, operation: AluOperation) -> EmulatorCoreResult<usize> {
+        let shift_by_register = |shift_type| ShifterOperand::Register {
+            rm: operation.rd,
+            shift: Shift { shift_type, amount: ShiftAmount::Register(operation.rs) },
+        };
+        let (opcode, rn, operand2) = match operation.opcode {
+            ThumbAluOpcode::Multiply => return Ok(self.run_multiply(Multiply {
+                condition: Condition::Always,
+                accumulate: false,
+                set_condition_codes: true,
+                rd: operation.rd,
+                rn: 0,
+                // The multiplier (that decides the timing) is the destination
+                rs: operation.rd,
+                rm: operation.rs,
+            })),
+            ThumbAluOpcode::LogicalLeft =>
+                (AluOpcode::Move, 0, shift_by_register(ShiftType::LogicalLeft)),
+            ThumbAluOpcode::LogicalRight =>
+                (AluOpcode::Move, 0, shift_by_register(ShiftType::LogicalRight)),
+            ThumbAluOpcode::ArithmeticRight =>
+                (AluOpcode::Move, 0, shift_by_register(ShiftType::ArithmeticRight)),
+            ThumbAluOpcode::RotateRight =>
+                (AluOpcode::Move, 0, shift_by_register(ShiftType::RotateRight)),
+            // NEG is RSBS Rd, Rs, #0
+            ThumbAluOpcode::Negate => (AluOpcode::ReverseSubtract, operation.rs,
+                ShifterOperand::Immediate { value: 0, rotate: 0 }),
+            opcode => {
+                let opcode = match opcode {
+                    ThumbAluOpcode::And => AluOpcode::And,
+                    ThumbAluOpcode::ExclusiveOr => AluOpcode::ExclusiveOr,
+                    ThumbAluOpcode::AddCarry => AluOpcode::AddCarry,
+                    ThumbAluOpcode::SubtractCarry => AluOpcode::SubtractCarry,
+                    ThumbAluOpcode::Test => AluOpcode::Test,
+                    ThumbAluOpcode::Compare => AluOpcode::Compare,
+                    ThumbAluOpcode::CompareNegative => AluOpcode::CompareNegative,
+                    ThumbAluOpcode::Or => AluOpcode::Or,
+                    ThumbAluOpcode::BitClear => AluOpcode::BitClear,
+                    ThumbAluOpcode::MoveNot => AluOpcode::MoveNot,
+                    _ => panic!("Programming error"),
+                };
+                (opcode, operation.rd, thumb_register_operand(operation.rs))
+            },
+        };
+        self.run_data_processing(DataProcessing {
+            condition: Condition::Always,
+            opcode,
+            set_condition_codes: true,
+            rn,
+            rd: operation.rd,
+            operand2,
+        })
+    }
+}
+
+/// A plain register operand without any shift
+fn thumb_register_operand(rm: u8) -> ShifterOperand {
+    ShifterOperand::Register {
+        rm,
+        shift: Shift { shift_type: ShiftType::LogicalLeft, amount: ShiftAmount::Immediate(0) },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taken_branch_charges_the_discarded_fetch() {
+        // A Thumb B over the next instruction, in ROM with WAITCNT at 0 (N = 5 and S = 3)
+        let rom = vec![0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut memory = GBAMemory::from_bytes(rom, vec![0; 16 << 10]).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.registers.set_thumb_state(true);
+        cpu.registers.r15 = 0x0800_0000;
+        cpu.fill_pipeline(&mut memory);
+
+        // The fetch that gets thrown away (S), then the refill from the target (N + S)
+        assert_eq!(cpu.run_next_instruction(&mut memory).unwrap(), 3 + 5 + 3);
+        assert_eq!(cpu.pipeline.decoded_address(), Some(0x0800_0004));
+    }
+}
@@ -0,0 +1,59 @@
+use brave_emulator_common::instruction_sets::ShiftType;
+
+/// Runs the value through the barrel shifter, giving back the result and the shifter carry out.
+/// The amount follows the rules for a register specified shift:
+/// 0 leaves everything alone and anything past 32 shifts the whole value out.
+pub fn shift(shift_type: ShiftType, value: u32, amount: u32, carry: bool) -> (u32, bool) {
+    if amount == 0 && shift_type != ShiftType::RotateRightExtended {
+        return (value, carry);
+    }
+
+    match shift_type {
+        ShiftType::LogicalLeft => match amount {
+            1..=31 => (value << amount, (value >> (32 - amount)) & 1 == 1),
+            32 => (0, value & 1 == 1),
+            _ => (0, false),
+        },
+        ShiftType::LogicalRight => match amount {
+            1..=31 => (value >> amount, (value >> (amount - 1)) & 1 == 1),
+            32 => (0, value >> 31 == 1),
+            _ => (0, false),
+        },
+        ShiftType::ArithmeticRight => match amount {
+            1..=31 => (((value as i32) >> amount) as u32, (value >> (amount - 1)) & 1 == 1),
+            // Everything gets filled with the sign bit
+            _ => (((value as i32) >> 31) as u32, value >> 31 == 1),
+        },
+        ShiftType::RotateRight => match amount % 32 {
+            // Multiples of 32 keep the value but still update the carry
+            0 => (value, value >> 31 == 1),
+            amount => (value.rotate_right(amount), (value >> (amount - 1)) & 1 == 1),
+        },
+        ShiftType::RotateRightExtended => (((carry as u32) << 31) | (value >> 1), value & 1 == 1),
+    }
+}
+
+/// Adds the 2 values and the carry in, giving back the result, the carry out and the overflow.
+/// Subtraction is done with the inverted operand (the carry is then NOT borrow).
+pub fn add_with_carry(a: u32, b: u32, carry: bool) -> (u32, bool, bool) {
+    let full_result = a as u64 + b as u64 + carry as u64;
+    let result = full_result as u32;
+    // Overflow happens when both operands have the same sign but the result doesn't
+    let overflow = (!(a ^ b) & (a ^ result)) >> 31 == 1;
+    (result, full_result > 0xFFFF_FFFF, overflow)
+}
+
+/// The internal cycles the multiplier needs, which ends early once the rest of the multiplier
+/// is only sign bits (or only zeros for an unsigned multiply).
+pub fn multiply_cycles(multiplier: u32, signed: bool) -> usize {
+    let is_done = |rest: u32, mask: u32| rest == 0 || (signed && rest == mask);
+    if is_done(multiplier & 0xFFFF_FF00, 0xFFFF_FF00) {
+        1
+    } else if is_done(multiplier & 0xFFFF_0000, 0xFFFF_0000) {
+        2
+    } else if is_done(multiplier & 0xFF00_0000, 0xFF00_0000) {
+        3
+    } else {
+        4
+    }
+}
@@ -0,0 +1,68 @@
+use super::register::Mode;
+
+/// The exceptions that the ARM7TDMI can take
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Exception {
+    Reset,
+    /// An instruction that couldn't be decoded
+    Undefined,
+    SoftwareInterrupt,
+    /// An instruction fetch that failed, which is only taken if the instruction gets executed
+    PrefetchAbort,
+    /// A load or store that failed
+    DataAbort,
+    Irq,
+    Fiq,
+}
+impl Exception {
+    /// The address in the BIOS that handles the exception
+    pub fn vector(self) -> u32 {
+        match self {
+            Exception::Reset => 0x00,
+            Exception::Undefined => 0x04,
+            Exception::SoftwareInterrupt => 0x08,
+            Exception::PrefetchAbort => 0x0C,
+            Exception::DataAbort => 0x10,
+            Exception::Irq => 0x18,
+            Exception::Fiq => 0x1C,
+        }
+    }
+
+    /// The mode that the CPU switches to for the exception
+    pub fn mode(self) -> Mode {
+        match self {
+            Exception::Reset |
+            Exception::SoftwareInterrupt => Mode::Supervisor,
+            Exception::Undefined => Mode::Undefined,
+            Exception::PrefetchAbort |
+            Exception::DataAbort => Mode::Abort,
+            Exception::Irq => Mode::Irq,
+            Exception::Fiq => Mode::Fiq,
+        }
+    }
+
+    /// IRQs are disabled by every exception, but FIQs are only disabled by a reset or an FIQ
+    pub fn disables_fiq(self) -> bool {
+        matches!(self, Exception::Reset | Exception::Fiq)
+    }
+
+    /// The value of the LR for the handler.
+    /// The address is of the instruction that caused the exception,
+    /// or the instruction that would have run next for an interrupt.
+    pub fn return_address(self, address: u32, is_thumb: bool) -> u32 {
+        let instruction_size = if is_thumb { 2 } else { 4 };
+        match self {
+            // Returns with "MOVS PC, LR" to the instruction after
+            Exception::Undefined |
+            Exception::SoftwareInterrupt => address.wrapping_add(instruction_size),
+            // Returns with "SUBS PC, LR, #4" to the same instruction
+            Exception::PrefetchAbort |
+            Exception::Irq |
+            Exception::Fiq => address.wrapping_add(4),
+            // Returns with "SUBS PC, LR, #8" to retry the same instruction
+            Exception::DataAbort => address.wrapping_add(8),
+            // There isn't anything to return to
+            Exception::Reset => address,
+        }
+    }
+}
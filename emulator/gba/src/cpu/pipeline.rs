@@ -0,0 +1,129 @@
+use brave_emulator_common::instruction_sets::{Arm32, Thumb32};
+
+use crate::{
+    bus::AccessType,
+    memory::GBAMemory,
+};
+
+use super::ARCHITECTURE;
+
+/// The 3 stages of the ARM7TDMI (fetch, decode and execute).
+/// R15 always points at the next fetch, so the executing instruction sees it 2 instructions
+/// ahead (PC+8 for ARM and PC+4 for Thumb).
+pub struct Pipeline {
+    /// The address and the raw instruction that will be decoded next.
+    /// The instruction is missing if the fetch failed.
+    fetched: Option<(u32, Option<u32>)>,
+    /// The address and the instruction that will be executed next
+    decoded: Option<(u32, CpuInstruction)>,
+    /// If the next fetch carries on from the last one
+    sequential: bool,
+}
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline {
+            fetched: None,
+            decoded: None,
+            sequential: false,
+        }
+    }
+
+    /// Throws away everything in the pipeline, so that the next fetch is non-sequential
+    pub fn flush(&mut self) {
+        self.fetched = None;
+        self.decoded = None;
+        self.sequential = false;
+    }
+
+    /// The next fetch is non-sequential, since a data access moved the bus somewhere else
+    pub fn interrupt_sequence(&mut self) {
+        self.sequential = false;
+    }
+
+    /// Nothing is ready to execute after a flush until the pipeline gets refilled
+    pub fn is_empty(&self) -> bool {
+        self.decoded.is_none()
+    }
+
+    /// Fetches the instruction at the PC and moves the PC to the next one.
+    /// Gives back the cycles that the fetch took.
+    pub fn fetch(&mut self, memory: &mut GBAMemory, pc: &mut u32, is_thumb: bool) -> usize {
+        let access_type = if self.sequential {
+            AccessType::Sequential
+        } else {
+            AccessType::NonSequential
+        };
+
+        let address = *pc;
+        // A failed fetch only aborts if the instruction makes it to the execute stage
+        let (instruction, cycles) = match memory.fetch_opcode(address, is_thumb, access_type) {
+            Ok((instruction, cycles)) => (Some(instruction), cycles),
+            Err(_) => (None, 1),
+        };
+        self.fetched = Some((address, instruction));
+        self.sequential = true;
+        *pc = address.wrapping_add(if is_thumb { 2 } else { 4 });
+        cycles
+    }
+
+    /// Moves the fetched instruction into the decode stage
+    pub fn decode(&mut self, is_thumb: bool) {
+        self.decoded = self.fetched.take().map(|(address, instruction)| {
+            let instruction = match instruction {
+                Some(instruction) => instruction,
+                None => return (address, CpuInstruction::PrefetchAbort),
+            };
+            let decoded = if is_thumb {
+                Thumb32::find_instruction(instruction as u16, ARCHITECTURE)
+                    .map(CpuInstruction::Thumb)
+            } else {
+                Arm32::find_instruction(instruction, ARCHITECTURE).map(CpuInstruction::Arm)
+            };
+            // It's only a problem if it actually gets executed
+            (address, decoded.unwrap_or(CpuInstruction::Undecodable))
+        });
+    }
+
+    /// The address of the instruction that will be executed next
+    pub fn decoded_address(&self) -> Option<u32> {
+        self.decoded.as_ref().map(|(address, _)| *address)
+    }
+
+    /// Takes the instruction out of the decode stage (with its address) to execute it
+    pub fn take_decoded(&mut self) -> Option<(u32, CpuInstruction)> {
+        self.decoded.take()
+    }
+}
+
+// Depending on which mode is currently set
+pub enum CpuInstruction {
+    Arm(Arm32),
+    Thumb(Thumb32),
+    /// An instruction that couldn't be decoded
+    Undecodable,
+    /// The fetch for this instruction failed
+    PrefetchAbort,
+}
+impl CpuInstruction {
+    /// If the instruction loads or stores, using the bus between instruction fetches
+    pub fn accesses_memory(&self) -> bool {
+        match self {
+            CpuInstruction::Arm(arm) => matches!(arm,
+                Arm32::SingleDataTransfer(_) |
+                Arm32::HalfwordDataTransfer(_) |
+                Arm32::BlockDataTransfer(_) |
+                Arm32::Swap(_)),
+            CpuInstruction::Thumb(thumb) => matches!(thumb,
+                Thumb32::PcRelativeLoad(_) |
+                Thumb32::LoadStoreRegisterOffset(_) |
+                Thumb32::LoadStoreSignExtended(_) |
+                Thumb32::LoadStoreImmediateOffset(_) |
+                Thumb32::LoadStoreHalfword(_) |
+                Thumb32::SpRelativeLoadStore(_) |
+                Thumb32::PushPop(_) |
+                Thumb32::MultipleLoadStore(_)),
+            CpuInstruction::Undecodable |
+            CpuInstruction::PrefetchAbort => false,
+        }
+    }
+}
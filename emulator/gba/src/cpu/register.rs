@@ -1,84 +1,307 @@
-use brave_emulator_common::instruction_sets::Condition;
-
-const THUMB_STATE_BIT: u32 = 0b00000000_00000000_00000000_00100000;
-
-#[derive(Default)]
-pub struct RegisterSet {
-    pub r0: u32, pub r1: u32, pub r2: u32, pub r3: u32,
-    pub r4: u32, pub r5: u32, pub r6: u32, pub r7: u32,
-    pub r8: u32, pub r9: u32, pub r10: u32, pub r11: u32, pub r12: u32,
-    /// Register 13 can also be used as a Stack Pointer
-    pub r13: u32,
-    /// Register 14 is the Link Register
-    pub r14: u32,
-    /// Register 15 is the Program Counter
-    pub r15: u32,
-
-    // Fast Interrupt specific registers
-    pub r8_fiq: u32, pub r9_fiq: u32,
-    pub r10_fiq: u32, pub r11_fiq: u32,
-    pub r12_fiq: u32, pub r13_fiq: u32,
-    pub r14_fiq: u32,
-
-    // Supervisor specific registers
-    pub r13_scv: u32, pub r14_scv: u32,
-
-    // Abort specific registers
-    pub r13_abt: u32, pub r14_abt: u32,
-
-    // Interrupt specific registers
-    pub r13_irq: u32, pub r14_irq: u32,
-
-    // Undefined specific registers
-    pub r13_und: u32, pub r14_und: u32,
-
-    /// The current Program Status Register
-    cpsr: u32,
-
-    // Program status registers specific to each mode
-    spsr_fiq: u32,
-    spsr_svc: u32,
-    spsr_abt: u32,
-    spsr_irq: u32,
-    spsr_und: u32,
-}
-impl RegisterSet {
-    pub fn does_condition_pass(&self, condition: Condition) -> bool {
-        const NEGATIVE_BIT: u32 = 0b10000000_00000000_00000000_00000000;
-        const ZERO_BIT: u32 = 0b01000000_00000000_00000000_00000000;
-        const CARRY_BIT: u32 = 0b00100000_00000000_00000000_00000000;
-        const OVERFLOW_BIT: u32 = 0b00010000_00000000_00000000_00000000;
-        match condition {
-            Condition::Equal => self.cpsr & ZERO_BIT == ZERO_BIT,
-            Condition::NEqual => self.cpsr & ZERO_BIT == 0,
-            Condition::CarrySet => self.cpsr & CARRY_BIT == CARRY_BIT,
-            Condition::CarryClear => self.cpsr & CARRY_BIT == 0,
-            Condition::Minus => self.cpsr & NEGATIVE_BIT == NEGATIVE_BIT,
-            Condition::Positive => self.cpsr & NEGATIVE_BIT == 0,
-            Condition::VSet => self.cpsr & OVERFLOW_BIT == OVERFLOW_BIT,
-            Condition::VClear => self.cpsr & OVERFLOW_BIT == 0,
-            Condition::UHigh => self.cpsr & CARRY_BIT == CARRY_BIT && self.cpsr & ZERO_BIT == 0,
-            Condition::ULow => self.cpsr & CARRY_BIT == 0 || self.cpsr & ZERO_BIT == ZERO_BIT,
-            Condition::GreaterEqual =>
-                // The N and V bit must be equal, so shift the N bit to the same place as the V bit
-                (self.cpsr & NEGATIVE_BIT >> 3) == (self.cpsr & OVERFLOW_BIT),
-            Condition::Less =>
-                (self.cpsr & NEGATIVE_BIT >> 3) != (self.cpsr & OVERFLOW_BIT),
-            Condition::Greater => self.cpsr & ZERO_BIT == 0 &&
-                (self.cpsr & NEGATIVE_BIT >> 3) == (self.cpsr & OVERFLOW_BIT),
-            Condition::LessEqual => self.cpsr & ZERO_BIT == ZERO_BIT ||
-                (self.cpsr & NEGATIVE_BIT >> 3) != (self.cpsr & OVERFLOW_BIT),
-            Condition::Always => true,
-            Condition::AllSet => false,
-        }
-    }
-
-    pub fn get_thumb_state(&self) -> bool { self.cpsr & THUMB_STATE_BIT == THUMB_STATE_BIT }
-    pub fn set_thumb_state(&mut self, state: bool) {
-        if state {
-            self.cpsr |= THUMB_STATE_BIT;
-        } else {
-            self.cpsr &= !THUMB_STATE_BIT;
-        }
-    }
-}
+use brave_emulator_common::instruction_sets::Condition;
+
+const NEGATIVE_BIT: u32 = 0b10000000_00000000_00000000_00000000;
+const ZERO_BIT: u32 = 0b01000000_00000000_00000000_00000000;
+const CARRY_BIT: u32 = 0b00100000_00000000_00000000_00000000;
+const OVERFLOW_BIT: u32 = 0b00010000_00000000_00000000_00000000;
+const FLAG_BITS: u32 = 0b11111111_00000000_00000000_00000000;
+const IRQ_DISABLE_BIT: u32 = 0b00000000_00000000_00000000_10000000;
+const FIQ_DISABLE_BIT: u32 = 0b00000000_00000000_00000000_01000000;
+const THUMB_STATE_BIT: u32 = 0b00000000_00000000_00000000_00100000;
+const MODE_BITS: u32 = 0b00000000_00000000_00000000_00011111;
+
+#[derive(Default)]
+pub struct RegisterSet {
+    pub r0: u32, pub r1: u32, pub r2: u32, pub r3: u32,
+    pub r4: u32, pub r5: u32, pub r6: u32, pub r7: u32,
+    pub r8: u32, pub r9: u32, pub r10: u32, pub r11: u32, pub r12: u32,
+    /// Register 13 can also be used as a Stack Pointer
+    pub r13: u32,
+    /// Register 14 is the Link Register
+    pub r14: u32,
+    /// Register 15 is the Program Counter
+    pub r15: u32,
+
+    // Fast Interrupt specific registers
+    pub r8_fiq: u32, pub r9_fiq: u32,
+    pub r10_fiq: u32, pub r11_fiq: u32,
+    pub r12_fiq: u32, pub r13_fiq: u32,
+    pub r14_fiq: u32,
+
+    // Supervisor specific registers
+    pub r13_scv: u32, pub r14_scv: u32,
+
+    // Abort specific registers
+    pub r13_abt: u32, pub r14_abt: u32,
+
+    // Interrupt specific registers
+    pub r13_irq: u32, pub r14_irq: u32,
+
+    // Undefined specific registers
+    pub r13_und: u32, pub r14_und: u32,
+
+    /// The current Program Status Register
+    cpsr: u32,
+
+    // Program status registers specific to each mode
+    spsr_fiq: u32,
+    spsr_svc: u32,
+    spsr_abt: u32,
+    spsr_irq: u32,
+    spsr_und: u32,
+}
+impl RegisterSet {
+    /// Makes the registers as they are after a reset
+    pub fn new() -> RegisterSet {
+        RegisterSet {
+            cpsr: Mode::Supervisor.to_bits() | IRQ_DISABLE_BIT | FIQ_DISABLE_BIT,
+            ..RegisterSet::default()
+        }
+    }
+
+    /// Gets one of the currently visible registers (0-15)
+    pub fn get(&self, register: u8) -> u32 {
+        match register {
+            0 => self.r0, 1 => self.r1, 2 => self.r2, 3 => self.r3,
+            4 => self.r4, 5 => self.r5, 6 => self.r6, 7 => self.r7,
+            8 => self.r8, 9 => self.r9, 10 => self.r10, 11 => self.r11,
+            12 => self.r12, 13 => self.r13, 14 => self.r14, 15 => self.r15,
+            _ => panic!("Programming error"),
+        }
+    }
+    /// Sets one of the currently visible registers (0-15)
+    pub fn set(&mut self, register: u8, value: u32) {
+        match register {
+            0 => self.r0 = value, 1 => self.r1 = value, 2 => self.r2 = value, 3 => self.r3 = value,
+            4 => self.r4 = value, 5 => self.r5 = value, 6 => self.r6 = value, 7 => self.r7 = value,
+            8 => self.r8 = value, 9 => self.r9 = value, 10 => self.r10 = value,
+            11 => self.r11 = value, 12 => self.r12 = value, 13 => self.r13 = value,
+            14 => self.r14 = value, 15 => self.r15 = value,
+            _ => panic!("Programming error"),
+        }
+    }
+
+    /// Gets one of the user mode registers (0-15), no matter which mode we are in.
+    /// The banked registers are swapped in when the mode changes,
+    /// so the user registers live in the bank of the current mode.
+    pub fn get_user_bank(&self, register: u8) -> u32 {
+        match (self.get_mode(), register) {
+            (Mode::Fiq, 8) => self.r8_fiq, (Mode::Fiq, 9) => self.r9_fiq,
+            (Mode::Fiq, 10) => self.r10_fiq, (Mode::Fiq, 11) => self.r11_fiq,
+            (Mode::Fiq, 12) => self.r12_fiq, (Mode::Fiq, 13) => self.r13_fiq,
+            (Mode::Fiq, 14) => self.r14_fiq,
+            (Mode::Supervisor, 13) => self.r13_scv, (Mode::Supervisor, 14) => self.r14_scv,
+            (Mode::Abort, 13) => self.r13_abt, (Mode::Abort, 14) => self.r14_abt,
+            (Mode::Irq, 13) => self.r13_irq, (Mode::Irq, 14) => self.r14_irq,
+            (Mode::Undefined, 13) => self.r13_und, (Mode::Undefined, 14) => self.r14_und,
+            _ => self.get(register),
+        }
+    }
+    /// Sets one of the user mode registers (0-15), no matter which mode we are in
+    pub fn set_user_bank(&mut self, register: u8, value: u32) {
+        match (self.get_mode(), register) {
+            (Mode::Fiq, 8) => self.r8_fiq = value, (Mode::Fiq, 9) => self.r9_fiq = value,
+            (Mode::Fiq, 10) => self.r10_fiq = value, (Mode::Fiq, 11) => self.r11_fiq = value,
+            (Mode::Fiq, 12) => self.r12_fiq = value, (Mode::Fiq, 13) => self.r13_fiq = value,
+            (Mode::Fiq, 14) => self.r14_fiq = value,
+            (Mode::Supervisor, 13) => self.r13_scv = value,
+            (Mode::Supervisor, 14) => self.r14_scv = value,
+            (Mode::Abort, 13) => self.r13_abt = value, (Mode::Abort, 14) => self.r14_abt = value,
+            (Mode::Irq, 13) => self.r13_irq = value, (Mode::Irq, 14) => self.r14_irq = value,
+            (Mode::Undefined, 13) => self.r13_und = value,
+            (Mode::Undefined, 14) => self.r14_und = value,
+            _ => self.set(register, value),
+        }
+    }
+
+    pub fn get_cpsr(&self) -> u32 { self.cpsr }
+    /// Sets the CPSR, swapping in the banked registers if the mode changes
+    pub fn set_cpsr(&mut self, cpsr: u32) {
+        let old_mode = self.get_mode();
+        let new_mode = Mode::from_bits(cpsr & MODE_BITS);
+        if old_mode != new_mode {
+            // Swapping the old mode's bank back puts the user registers where they belong
+            self.swap_bank(old_mode);
+            self.swap_bank(new_mode);
+        }
+        self.cpsr = cpsr;
+    }
+    /// The SPSR of the current mode. User and System modes don't have one, so they get the CPSR.
+    pub fn get_spsr(&self) -> u32 {
+        match self.get_mode() {
+            Mode::Fiq => self.spsr_fiq,
+            Mode::Supervisor => self.spsr_svc,
+            Mode::Abort => self.spsr_abt,
+            Mode::Irq => self.spsr_irq,
+            Mode::Undefined => self.spsr_und,
+            Mode::User | Mode::System => self.cpsr,
+        }
+    }
+    /// Sets the SPSR of the current mode. User and System modes don't have one, so it's ignored.
+    pub fn set_spsr(&mut self, spsr: u32) {
+        match self.get_mode() {
+            Mode::Fiq => self.spsr_fiq = spsr,
+            Mode::Supervisor => self.spsr_svc = spsr,
+            Mode::Abort => self.spsr_abt = spsr,
+            Mode::Irq => self.spsr_irq = spsr,
+            Mode::Undefined => self.spsr_und = spsr,
+            Mode::User | Mode::System => {},
+        }
+    }
+    /// Writes only the bytes of the CPSR (or SPSR) that are in the field mask (bit 0 is bits 7-0).
+    /// User mode can only write the flags, and the Thumb state can never be changed from here.
+    pub fn write_psr_fields(&mut self, spsr: bool, value: u32, field_mask: u8) {
+        let mut mask = (0..4)
+            .filter(|byte| field_mask & (1 << byte) != 0)
+            .fold(0, |mask, byte| mask | (0xFF << (byte * 8)));
+        if spsr {
+            let spsr = self.get_spsr();
+            self.set_spsr((spsr & !mask) | (value & mask));
+        } else {
+            if self.get_mode() == Mode::User {
+                mask &= FLAG_BITS;
+            }
+            mask &= !THUMB_STATE_BIT;
+            self.set_cpsr((self.cpsr & !mask) | (value & mask));
+        }
+    }
+    /// Moves the SPSR of the current mode back into the CPSR (when returning from an exception)
+    pub fn restore_cpsr(&mut self) {
+        self.set_cpsr(self.get_spsr());
+    }
+
+    pub fn get_mode(&self) -> Mode { Mode::from_bits(self.cpsr & MODE_BITS) }
+    /// Switches to the mode, keeping the rest of the CPSR
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.set_cpsr((self.cpsr & !MODE_BITS) | mode.to_bits());
+    }
+
+    pub fn is_irq_disabled(&self) -> bool { self.cpsr & IRQ_DISABLE_BIT == IRQ_DISABLE_BIT }
+    pub fn set_irq_disabled(&mut self, disabled: bool) { self.set_flag(IRQ_DISABLE_BIT, disabled); }
+    pub fn is_fiq_disabled(&self) -> bool { self.cpsr & FIQ_DISABLE_BIT == FIQ_DISABLE_BIT }
+    pub fn set_fiq_disabled(&mut self, disabled: bool) { self.set_flag(FIQ_DISABLE_BIT, disabled); }
+
+    pub fn does_condition_pass(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Equal => self.cpsr & ZERO_BIT == ZERO_BIT,
+            Condition::NEqual => self.cpsr & ZERO_BIT == 0,
+            Condition::CarrySet => self.cpsr & CARRY_BIT == CARRY_BIT,
+            Condition::CarryClear => self.cpsr & CARRY_BIT == 0,
+            Condition::Minus => self.cpsr & NEGATIVE_BIT == NEGATIVE_BIT,
+            Condition::Positive => self.cpsr & NEGATIVE_BIT == 0,
+            Condition::VSet => self.cpsr & OVERFLOW_BIT == OVERFLOW_BIT,
+            Condition::VClear => self.cpsr & OVERFLOW_BIT == 0,
+            Condition::UHigh => self.cpsr & CARRY_BIT == CARRY_BIT && self.cpsr & ZERO_BIT == 0,
+            Condition::ULow => self.cpsr & CARRY_BIT == 0 || self.cpsr & ZERO_BIT == ZERO_BIT,
+            Condition::GreaterEqual =>
+                // The N and V bit must be equal, so shift the N bit to the same place as the V bit
+                ((self.cpsr & NEGATIVE_BIT) >> 3) == (self.cpsr & OVERFLOW_BIT),
+            Condition::Less =>
+                ((self.cpsr & NEGATIVE_BIT) >> 3) != (self.cpsr & OVERFLOW_BIT),
+            Condition::Greater => self.cpsr & ZERO_BIT == 0 &&
+                ((self.cpsr & NEGATIVE_BIT) >> 3) == (self.cpsr & OVERFLOW_BIT),
+            Condition::LessEqual => self.cpsr & ZERO_BIT == ZERO_BIT ||
+                ((self.cpsr & NEGATIVE_BIT) >> 3) != (self.cpsr & OVERFLOW_BIT),
+            Condition::Always => true,
+            Condition::AllSet => false,
+        }
+    }
+
+    pub fn get_carry_flag(&self) -> bool { self.cpsr & CARRY_BIT == CARRY_BIT }
+    /// Sets N and Z from the result, leaving C and V alone
+    pub fn set_result_flags(&mut self, result: u32) {
+        self.set_flag(NEGATIVE_BIT, result & NEGATIVE_BIT == NEGATIVE_BIT);
+        self.set_flag(ZERO_BIT, result == 0);
+    }
+    pub fn set_zero_flag(&mut self, zero: bool) { self.set_flag(ZERO_BIT, zero); }
+    pub fn set_carry_flag(&mut self, carry: bool) { self.set_flag(CARRY_BIT, carry); }
+    pub fn set_overflow_flag(&mut self, overflow: bool) { self.set_flag(OVERFLOW_BIT, overflow); }
+
+    pub fn get_thumb_state(&self) -> bool { self.cpsr & THUMB_STATE_BIT == THUMB_STATE_BIT }
+    pub fn set_thumb_state(&mut self, state: bool) {
+        if state {
+            self.cpsr |= THUMB_STATE_BIT;
+        } else {
+            self.cpsr &= !THUMB_STATE_BIT;
+        }
+    }
+}
+impl RegisterSet {
+    /// Exchanges the visible registers with the ones banked for the mode
+    fn swap_bank(&mut self, mode: Mode) {
+        use std::mem::swap;
+        match mode {
+            Mode::Fiq => {
+                swap(&mut self.r8, &mut self.r8_fiq);
+                swap(&mut self.r9, &mut self.r9_fiq);
+                swap(&mut self.r10, &mut self.r10_fiq);
+                swap(&mut self.r11, &mut self.r11_fiq);
+                swap(&mut self.r12, &mut self.r12_fiq);
+                swap(&mut self.r13, &mut self.r13_fiq);
+                swap(&mut self.r14, &mut self.r14_fiq);
+            },
+            Mode::Supervisor => {
+                swap(&mut self.r13, &mut self.r13_scv);
+                swap(&mut self.r14, &mut self.r14_scv);
+            },
+            Mode::Abort => {
+                swap(&mut self.r13, &mut self.r13_abt);
+                swap(&mut self.r14, &mut self.r14_abt);
+            },
+            Mode::Irq => {
+                swap(&mut self.r13, &mut self.r13_irq);
+                swap(&mut self.r14, &mut self.r14_irq);
+            },
+            Mode::Undefined => {
+                swap(&mut self.r13, &mut self.r13_und);
+                swap(&mut self.r14, &mut self.r14_und);
+            },
+            Mode::User | Mode::System => {},
+        }
+    }
+
+    fn set_flag(&mut self, flag: u32, state: bool) {
+        if state {
+            self.cpsr |= flag;
+        } else {
+            self.cpsr &= !flag;
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    User,
+    Fiq,
+    Irq,
+    Supervisor,
+    Abort,
+    Undefined,
+    /// Privileged mode that uses the user registers
+    System,
+}
+impl Mode {
+    fn from_bits(bits: u32) -> Mode {
+        match bits {
+            0b10001 => Mode::Fiq,
+            0b10010 => Mode::Irq,
+            0b10011 => Mode::Supervisor,
+            0b10111 => Mode::Abort,
+            0b11011 => Mode::Undefined,
+            0b11111 => Mode::System,
+            // Anything else is unpredictable, so act like user mode
+            _ => Mode::User,
+        }
+    }
+    fn to_bits(self) -> u32 {
+        match self {
+            Mode::User => 0b10000,
+            Mode::Fiq => 0b10001,
+            Mode::Irq => 0b10010,
+            Mode::Supervisor => 0b10011,
+            Mode::Abort => 0b10111,
+            Mode::Undefined => 0b11011,
+            Mode::System => 0b11111,
+        }
+    }
+}
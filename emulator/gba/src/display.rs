@@ -0,0 +1,138 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+};
+
+use crate::interrupt::{Interrupt, InterruptController};
+
+/// The cycles that a line is drawn for, before the H-Blank
+const HDRAW_CYCLES: usize = 960;
+/// The cycles that the H-Blank lasts, at the end of every line
+const HBLANK_CYCLES: usize = 272;
+/// The lines that get drawn, before the V-Blank
+const VISIBLE_LINES: u16 = 160;
+/// The lines in a whole frame, including the V-Blank
+const TOTAL_LINES: u16 = 228;
+
+/// OAM can be used during the H-Blank when this is set in DISPCNT
+const HBLANK_INTERVAL_FREE_BIT: u16 = 0b00000000_00100000;
+
+const VBLANK_FLAG_BIT: u16 = 0b00000000_00000001;
+const HBLANK_FLAG_BIT: u16 = 0b00000000_00000010;
+const VCOUNT_FLAG_BIT: u16 = 0b00000000_00000100;
+const VBLANK_IRQ_BIT: u16 = 0b00000000_00001000;
+const HBLANK_IRQ_BIT: u16 = 0b00000000_00010000;
+const VCOUNT_IRQ_BIT: u16 = 0b00000000_00100000;
+/// The flags in DISPSTAT come from the timing, so they can't be written
+const DISPSTAT_WRITABLE_BITS: u16 = 0b11111111_00111000;
+
+/// The timing side of the PPU, which decides when the video memory is busy.
+/// It holds DISPCNT (0x04000000), DISPSTAT (0x04000004) and VCOUNT (0x04000006).
+/// The core's scheduler tells it when the H-Blank starts and when the line is finished.
+pub struct Display {
+    interrupts: Rc<RefCell<InterruptController>>,
+    control: u16,
+    /// Only the settings in DISPSTAT, since the flags come from the timing
+    status: u16,
+    /// The line being drawn (VCOUNT)
+    line: u16,
+    /// If the line is done being drawn
+    is_hblank: bool,
+}
+impl Display {
+    pub fn new(interrupts: Rc<RefCell<InterruptController>>) -> Display {
+        Display {
+            interrupts,
+            control: 0,
+            status: 0,
+            line: 0,
+            is_hblank: false,
+        }
+    }
+
+    /// The cycles from the start of a line until its H-Blank
+    pub fn cycles_until_hblank() -> usize { HDRAW_CYCLES }
+
+    /// Gives back the cycles until the line is finished
+    pub fn start_hblank(&mut self) -> usize {
+        self.is_hblank = true;
+        self.raise_if_enabled(HBLANK_IRQ_BIT, Interrupt::HBlank);
+        HBLANK_CYCLES
+    }
+    /// Moves on to the next line, giving back the cycles until its H-Blank
+    pub fn finish_line(&mut self) -> usize {
+        self.is_hblank = false;
+        self.line = (self.line + 1) % TOTAL_LINES;
+        if self.line == VISIBLE_LINES {
+            self.raise_if_enabled(VBLANK_IRQ_BIT, Interrupt::VBlank);
+        }
+        if self.line == self.vcount_setting() {
+            self.raise_if_enabled(VCOUNT_IRQ_BIT, Interrupt::VCount);
+        }
+        HDRAW_CYCLES
+    }
+
+    /// If the PPU is drawing, so that the CPU has to wait an extra cycle for the video memory
+    pub fn is_drawing(&self) -> bool {
+        self.line < VISIBLE_LINES && !self.is_hblank
+    }
+
+    /// If the PPU is using OAM, so that the CPU can't write to it.
+    /// The H-Blank can be freed up for the CPU with DISPCNT.
+    pub fn is_oam_locked(&self) -> bool {
+        self.line < VISIBLE_LINES &&
+            (!self.is_hblank || self.control & HBLANK_INTERVAL_FREE_BIT == 0)
+    }
+
+    /// Reads a byte, where the offset is from the start of DISPCNT
+    pub fn read_byte(&self, offset: usize) -> u8 {
+        match offset {
+            0x0 => self.control as u8,
+            0x1 => (self.control >> 8) as u8,
+            0x4 => self.get_status() as u8,
+            0x5 => (self.get_status() >> 8) as u8,
+            0x6 => self.line as u8,
+            // The rest of VCOUNT is unused
+            _ => 0,
+        }
+    }
+    /// Writes a byte, where the offset is from the start of DISPCNT
+    pub fn write_byte(&mut self, offset: usize, value: u8) {
+        match offset {
+            0x0 => self.control = (self.control & 0xFF00) | value as u16,
+            0x1 => self.control = (self.control & 0x00FF) | (value as u16) << 8,
+            0x4 => self.status =
+                ((self.status & 0xFF00) | value as u16) & DISPSTAT_WRITABLE_BITS,
+            0x5 => self.status =
+                ((self.status & 0x00FF) | (value as u16) << 8) & DISPSTAT_WRITABLE_BITS,
+            // VCOUNT can't be written
+            _ => {},
+        }
+    }
+}
+impl Display {
+    fn raise_if_enabled(&self, irq_bit: u16, interrupt: Interrupt) {
+        if self.status & irq_bit == irq_bit {
+            self.interrupts.borrow_mut().raise(interrupt);
+        }
+    }
+
+    /// The line that DISPSTAT is waiting for
+    fn vcount_setting(&self) -> u16 { self.status >> 8 }
+
+    /// DISPSTAT with the flags filled in from the timing
+    fn get_status(&self) -> u16 {
+        let mut status = self.status;
+        // The last line doesn't count as V-Blank
+        if self.line >= VISIBLE_LINES && self.line < TOTAL_LINES - 1 {
+            status |= VBLANK_FLAG_BIT;
+        }
+        if self.is_hblank {
+            status |= HBLANK_FLAG_BIT;
+        }
+        if self.line == self.vcount_setting() {
+            status |= VCOUNT_FLAG_BIT;
+        }
+        status
+    }
+}
@@ -0,0 +1,147 @@
+/// The offset of IE from the start of the interrupt registers
+const ENABLE_OFFSET: usize = 0x0;
+/// The offset of IF from the start of the interrupt registers
+const REQUEST_OFFSET: usize = 0x2;
+/// The offset of IME from the start of the interrupt registers
+const MASTER_ENABLE_OFFSET: usize = 0x8;
+/// Only the bottom 14 bits of IE and IF have a source
+const INTERRUPT_BITS: u16 = 0b00111111_11111111;
+
+/// Holds the IE, IF and IME registers, deciding when the CPU gets an IRQ
+pub struct InterruptController {
+    /// IE: The interrupts that are allowed to reach the CPU
+    enabled: u16,
+    /// IF: The interrupts that have been raised and not acknowledged yet
+    requested: u16,
+    /// IME: Nothing reaches the CPU without this set
+    master_enabled: bool,
+}
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            enabled: 0,
+            requested: 0,
+            master_enabled: false,
+        }
+    }
+
+    /// Sets the interrupt's bit in IF. It stays set until the game acknowledges it.
+    pub fn raise(&mut self, interrupt: Interrupt) {
+        self.requested |= interrupt.to_bit();
+    }
+
+    /// If the IRQ line to the CPU should be held. The CPU still needs CPSR.I to be clear.
+    pub fn is_irq_pending(&self) -> bool {
+        self.master_enabled && self.enabled & self.requested != 0
+    }
+
+    /// Reads a byte, where the offset is from the start of IE (0x04000200)
+    pub fn read_byte(&self, offset: usize) -> u8 {
+        match offset {
+            ENABLE_OFFSET => self.enabled as u8,
+            0x1 => (self.enabled >> 8) as u8,
+            REQUEST_OFFSET => self.requested as u8,
+            0x3 => (self.requested >> 8) as u8,
+            MASTER_ENABLE_OFFSET => self.master_enabled as u8,
+            // The rest of IME is unused
+            _ => 0,
+        }
+    }
+    /// Writes a byte, where the offset is from the start of IE (0x04000200).
+    /// Writing a 1 to a bit of IF acknowledges (clears) that interrupt.
+    pub fn write_byte(&mut self, offset: usize, value: u8) {
+        match offset {
+            ENABLE_OFFSET =>
+                self.enabled = ((self.enabled & 0xFF00) | value as u16) & INTERRUPT_BITS,
+            0x1 => self.enabled = ((self.enabled & 0x00FF) | (value as u16) << 8) & INTERRUPT_BITS,
+            REQUEST_OFFSET => self.requested &= !(value as u16),
+            0x3 => self.requested &= !((value as u16) << 8),
+            MASTER_ENABLE_OFFSET => self.master_enabled = value & 1 == 1,
+            _ => {},
+        }
+    }
+}
+
+/// Everything that can raise an IRQ, in the order of their bits in IE and IF
+// Only the display raises its interrupts so far, but the game can still enable all of them
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    HBlank,
+    /// The line counter matched the setting in DISPSTAT
+    VCount,
+    Timer0,
+    Timer1,
+    Timer2,
+    Timer3,
+    Serial,
+    Dma0,
+    Dma1,
+    Dma2,
+    Dma3,
+    Keypad,
+    GamePak,
+}
+impl Interrupt {
+    fn to_bit(self) -> u16 {
+        1 << (self as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_a_one_to_if_acknowledges() {
+        let mut controller = InterruptController::new();
+        controller.raise(Interrupt::VBlank);
+        controller.raise(Interrupt::Timer2);
+        controller.raise(Interrupt::GamePak);
+        assert_eq!(controller.read_byte(REQUEST_OFFSET), 0b0010_0001);
+        assert_eq!(controller.read_byte(REQUEST_OFFSET + 1), 0b0010_0000);
+
+        // Only the bits written as 1 get cleared
+        controller.write_byte(REQUEST_OFFSET, 0b0000_0001);
+        controller.write_byte(REQUEST_OFFSET + 1, 0);
+        assert_eq!(controller.read_byte(REQUEST_OFFSET), 0b0010_0000);
+        assert_eq!(controller.read_byte(REQUEST_OFFSET + 1), 0b0010_0000);
+        controller.write_byte(REQUEST_OFFSET + 1, 0xFF);
+        assert_eq!(controller.read_byte(REQUEST_OFFSET + 1), 0);
+    }
+
+    #[test]
+    fn ie_and_if_only_have_14_bits() {
+        let mut controller = InterruptController::new();
+        controller.write_byte(ENABLE_OFFSET, 0xFF);
+        controller.write_byte(ENABLE_OFFSET + 1, 0xFF);
+        assert_eq!(controller.read_byte(ENABLE_OFFSET), 0xFF);
+        assert_eq!(controller.read_byte(ENABLE_OFFSET + 1), 0x3F);
+
+        // The gamepak is the last source, at bit 13
+        controller.raise(Interrupt::GamePak);
+        assert_eq!(controller.read_byte(REQUEST_OFFSET + 1), 0x20);
+    }
+
+    #[test]
+    fn irq_needs_ime_ie_and_if() {
+        let mut controller = InterruptController::new();
+        controller.raise(Interrupt::Keypad);
+        assert!(!controller.is_irq_pending());
+
+        // Enabled, but not for the raised source
+        controller.write_byte(MASTER_ENABLE_OFFSET, 1);
+        controller.write_byte(ENABLE_OFFSET, 0xFF);
+        assert!(!controller.is_irq_pending());
+
+        controller.write_byte(ENABLE_OFFSET + 1, 0b0001_0000);
+        assert!(controller.is_irq_pending());
+
+        controller.write_byte(MASTER_ENABLE_OFFSET, 0);
+        assert!(!controller.is_irq_pending());
+        controller.write_byte(MASTER_ENABLE_OFFSET, 1);
+        controller.write_byte(REQUEST_OFFSET + 1, 0b0001_0000);
+        assert!(!controller.is_irq_pending());
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+};
+use brave_emulator_common::memory::MemoryHandler;
+
+use crate::{
+    display::Display,
+    interrupt::InterruptController,
+    wait_control::WaitControl,
+};
+
+/// DISPCNT is handled by the display
+const OFFSET_START_DISPLAY_CONTROL: usize = 0x000;
+const OFFSET_END_DISPLAY_CONTROL: usize = 0x001;
+/// DISPSTAT and VCOUNT are handled by the display
+const OFFSET_START_DISPLAY_STATUS: usize = 0x004;
+const OFFSET_END_DISPLAY_STATUS: usize = 0x007;
+/// IE and IF are handled by the interrupt controller
+const OFFSET_START_INTERRUPT_CONTROL: usize = 0x200;
+const OFFSET_END_INTERRUPT_CONTROL: usize = 0x203;
+/// WAITCNT (and its unused top half)
+const OFFSET_START_WAIT_CONTROL: usize = 0x204;
+const OFFSET_END_WAIT_CONTROL: usize = 0x207;
+/// IME (and its unused top half) are handled by the interrupt controller
+const OFFSET_START_INTERRUPT_MASTER: usize = 0x208;
+const OFFSET_END_INTERRUPT_MASTER: usize = 0x20B;
+
+/// Sends accesses to the IO registers to the hardware that owns them.
+/// Registers that aren't emulated yet just hold what gets written.
+pub struct IoRegisters {
+    bytes: Vec<u8>,
+    display: Rc<RefCell<Display>>,
+    interrupts: Rc<RefCell<InterruptController>>,
+    wait_control: Rc<RefCell<WaitControl>>,
+}
+impl IoRegisters {
+    pub fn new(size: usize, display: Rc<RefCell<Display>>,
+    interrupts: Rc<RefCell<InterruptController>>, wait_control: Rc<RefCell<WaitControl>>)
+    -> IoRegisters {
+        IoRegisters {
+            bytes: vec![0; size],
+            display,
+            interrupts,
+            wait_control,
+        }
+    }
+}
+impl IoRegisters {
+    fn read_byte(&self, offset: usize) -> u8 {
+        match offset {
+            OFFSET_START_DISPLAY_CONTROL..=OFFSET_END_DISPLAY_CONTROL |
+            OFFSET_START_DISPLAY_STATUS..=OFFSET_END_DISPLAY_STATUS =>
+                self.display.borrow().read_byte(offset - OFFSET_START_DISPLAY_CONTROL),
+            OFFSET_START_INTERRUPT_CONTROL..=OFFSET_END_INTERRUPT_CONTROL |
+            OFFSET_START_INTERRUPT_MASTER..=OFFSET_END_INTERRUPT_MASTER =>
+                self.interrupts.borrow().read_byte(offset - OFFSET_START_INTERRUPT_CONTROL),
+            OFFSET_START_WAIT_CONTROL..=OFFSET_END_WAIT_CONTROL =>
+                self.wait_control.borrow().read_byte(offset - OFFSET_START_WAIT_CONTROL),
+            _ => self.bytes[offset],
+        }
+    }
+    fn write_byte(&mut self, offset: usize, value: u8) {
+        match offset {
+            OFFSET_START_DISPLAY_CONTROL..=OFFSET_END_DISPLAY_CONTROL |
+            OFFSET_START_DISPLAY_STATUS..=OFFSET_END_DISPLAY_STATUS =>
+                self.display.borrow_mut().write_byte(offset - OFFSET_START_DISPLAY_CONTROL, value),
+            OFFSET_START_INTERRUPT_CONTROL..=OFFSET_END_INTERRUPT_CONTROL |
+            OFFSET_START_INTERRUPT_MASTER..=OFFSET_END_INTERRUPT_MASTER =>
+                self.interrupts.borrow_mut()
+                    .write_byte(offset - OFFSET_START_INTERRUPT_CONTROL, value),
+            OFFSET_START_WAIT_CONTROL..=OFFSET_END_WAIT_CONTROL =>
+                self.wait_control.borrow_mut()
+                    .write_byte(offset - OFFSET_START_WAIT_CONTROL, value),
+            _ => self.bytes[offset] = value,
+        }
+    }
+}
+impl MemoryHandler for IoRegisters {
+    // Registers can share a word, so each byte goes to its own register
+    fn read(&self, offset: usize, buffer: &mut [u8]) {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_byte(offset + i);
+        }
+    }
+    fn write(&mut self, offset: usize, buffer: &[u8]) {
+        for (i, byte) in buffer.iter().enumerate() {
+            self.write_byte(offset + i, *byte);
+        }
+    }
+}
@@ -1,32 +1,57 @@
+mod bus;
 mod cpu;
+mod display;
+mod interrupt;
+mod io;
 mod memory;
+mod prefetch;
 mod settings;
+mod wait_control;
 pub use self::{
     settings::{GBASettings, GBASettingsBuilder},
 };
 
 use std::{
-    time::{Duration},
+    time::{Duration, Instant},
 };
 use brave_emulator_common::{
     EmulatorCore,
     EmulatorCoreResult,
+    scheduler::Scheduler,
 };
 use brave_windowing::{Window};
 use crate::{
     cpu::Cpu,
+    display::Display,
     memory::GBAMemory,
 };
 
 // const SAVE_INTERVAL: Duration = Duration::from_secs(60);
 /// The GBA's CPU clock speed in hertz
 const CLOCK_SPEED: usize = 16_780_000;
+/// How long to wait between updates, which is about a frame
+const UPDATE_INTERVAL: Duration = Duration::from_micros(16_667);
+/// The most time that an update will catch up on (after being paused or stuck)
+const MAX_UPDATE_TIME: Duration = Duration::from_millis(100);
+
+/// The hardware events that the CPU has to stop for
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum GBAEvent {
+    HBlank,
+    LineFinished,
+}
 
 pub struct GBACore {
     settings: GBASettings,
     memory: GBAMemory,
     cpu: Cpu,
-    leftover_cycles: usize,
+    scheduler: Scheduler<GBAEvent>,
+    /// All of the real time that has been emulated, which sets where the cycles should be
+    emulated_time: Duration,
+    /// What the last update asked to wait for
+    until_next_update: Duration,
+    /// How long the last update took to run
+    last_update_length: Duration,
 }
 impl GBACore {
     pub fn create(settings: GBASettings, window: &Window) -> EmulatorCoreResult<GBACore> {
@@ -41,30 +66,67 @@ impl GBACore {
             settings,
             memory,
             cpu,
-            leftover_cycles: 0,
+            scheduler: Scheduler::new(),
+            emulated_time: Duration::from_secs(0),
+            until_next_update: Duration::from_secs(0),
+            last_update_length: Duration::from_secs(0),
         })
     }
 }
 impl GBACore {
+    /// Runs the CPU until the scheduler reaches the cycle,
+    /// stopping at each event since it can change what the CPU sees
+    fn run_until(&mut self, target: u64) -> EmulatorCoreResult<()> {
+        while self.scheduler.now() < target {
+            let run_until = self.scheduler.next_event_time()
+                .map_or(target, |time| time.min(target));
+            while self.scheduler.now() < run_until {
+                self.cpu.set_irq_line(self.memory.interrupts().is_irq_pending());
+                let cycles = self.cpu.run_next_instruction(&mut self.memory)?;
+                self.scheduler.advance(cycles);
+            }
+            self.handle_due_events();
+        }
+        Ok(())
+    }
+
+    fn handle_due_events(&mut self) {
+        while let Some((event, time)) = self.scheduler.pop_due() {
+            let (next_event, cycles) = match event {
+                GBAEvent::HBlank =>
+                    (GBAEvent::LineFinished, self.memory.display_mut().start_hblank()),
+                GBAEvent::LineFinished =>
+                    (GBAEvent::HBlank, self.memory.display_mut().finish_line()),
+            };
+            // From when it was due, so that an instruction running past it doesn't shift the timing
+            self.scheduler.schedule(next_event, time + cycles as u64);
+        }
+    }
 }
 impl EmulatorCore for GBACore {
     fn on_start(&mut self) -> EmulatorCoreResult<()> {
-        self.leftover_cycles += self.cpu.read_next_instruction(&mut self.memory)?;
+        self.scheduler.advance(self.cpu.reset(&mut self.memory));
+        self.scheduler.schedule_in(GBAEvent::HBlank, Display::cycles_until_hblank());
         Ok(())
     }
 
     fn on_update(&mut self, left_over: Duration) -> EmulatorCoreResult<Duration> {
-        let mut cycles = self.leftover_cycles;
-        self.leftover_cycles = 0;
+        let started = Instant::now();
+        // The real time since the last update started
+        let elapsed = self.last_update_length + self.until_next_update + left_over;
+        self.emulated_time += elapsed.min(MAX_UPDATE_TIME);
 
-        // TODO Figure out how many cycles we'll want to go
-        while cycles < 100 {
-            match self.cpu.run_next_instruction(&mut self.memory) {
-                Ok(ran_cycles) => cycles += ran_cycles,
-                Err(e) => panic!(e),
-            }
-        }
-        Ok(Duration::from_micros(0))
+        // Going by the total time means the rounding never adds up
+        let target = (self.emulated_time.as_nanos() * CLOCK_SPEED as u128 / 1_000_000_000) as u64;
+        let result = self.run_until(target);
+
+        // The next update comes straight away after an error, since there's no interval to wait
+        self.until_next_update = match result {
+            Ok(()) => UPDATE_INTERVAL,
+            Err(_) => Duration::from_secs(0),
+        };
+        self.last_update_length = started.elapsed();
+        result.map(|()| self.until_next_update)
     }
 
     fn on_pause(&mut self) -> EmulatorCoreResult<()> {
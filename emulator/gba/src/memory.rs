@@ -1,135 +1,388 @@
-use std::{
-    fs,
-    ops::{Deref, DerefMut},
-    path::{Path},
-};
-use brave_emulator_common::{
-    EmulatorCoreResult, EmulatorCoreError,
-    memory::{Memory, MemoryRegion},
-};
-
-/// The BIOS file will always be 16Kb
-const BIOS_FILE_SIZE: usize = 16 << 10;
-/// The work RAM on the board is 256KB
-const WRAM_ON_BOARD_SIZE: usize = 256 << 10;
-/// The work RAM on the chip is 32KB
-const WRAM_ON_CHIP_SIZE: usize = 32 << 10;
-/// 1KB for the IO registers
-const IO_REGISTERS_SIZE: usize = 1 << 10;
-/// 1KB for the palette RAM
-const PALETTE_RAM_SIZE: usize = 1 << 10;
-/// 96KB for the Video RAM
-const VRAM_SIZE: usize = 96 << 10;
-/// 1KB for the Object Attribute Memory
-const OAM_SIZE: usize = 1 << 10;
-/// The gamepak can be a max of 32MB
-const GAMEPAK_MAX_FILE_SIZE: usize = 32 << 20;
-/// 32KB for the SRAM
-const GAMEPAK_SRAM_SIZE: usize = 32 << 10;
-
-const ADDRESS_START_BIOS: usize = 0x0000_0000;
-const ADDRESS_END_BIOS: usize = ADDRESS_START_BIOS + BIOS_FILE_SIZE;
-const ADDRESS_START_WRAM_BOARD: usize = 0x0200_0000;
-const ADDRESS_END_WRAM_BOARD: usize = ADDRESS_START_WRAM_BOARD + WRAM_ON_BOARD_SIZE;
-const ADDRESS_START_WRAM_CHIP: usize = 0x0300_0000;
-const ADDRESS_END_WRAM_CHIP: usize = ADDRESS_START_WRAM_CHIP + WRAM_ON_CHIP_SIZE;
-const ADDRESS_START_IO_REGISTERS: usize = 0x0400_0000;
-const ADDRESS_END_IO_REGISTERS: usize = ADDRESS_START_IO_REGISTERS + IO_REGISTERS_SIZE;
-const ADDRESS_START_PALETTE: usize = 0x0500_0000;
-const ADDRESS_END_PALETTE: usize = ADDRESS_START_PALETTE + PALETTE_RAM_SIZE;
-const ADDRESS_START_VRAM: usize = 0x0600_0000;
-const ADDRESS_END_VRAM: usize = ADDRESS_START_VRAM + VRAM_SIZE;
-const ADDRESS_START_OAM: usize = 0x0700_0000;
-const ADDRESS_END_OAM: usize = ADDRESS_START_OAM + OAM_SIZE;
-const ADDRESS_START_GAMEPAK_WAIT0: usize = 0x0800_0000;
-const ADDRESS_END_GAMEPAK_WAIT0: usize = ADDRESS_START_GAMEPAK_WAIT0 + GAMEPAK_MAX_FILE_SIZE;
-const ADDRESS_START_GAMEPAK_WAIT1: usize = 0x0A00_0000;
-const ADDRESS_END_GAMEPAK_WAIT1: usize = ADDRESS_START_GAMEPAK_WAIT1 + GAMEPAK_MAX_FILE_SIZE;
-const ADDRESS_START_GAMEPAK_WAIT2: usize = 0x0C00_0000;
-const ADDRESS_END_GAMEPAK_WAIT2: usize = ADDRESS_START_GAMEPAK_WAIT2 + GAMEPAK_MAX_FILE_SIZE;
-const ADDRESS_START_GAMEPAK_SRAM: usize = 0x0E00_0000;
-const ADDRESS_END_GAMEPAK_SRAM: usize = ADDRESS_START_GAMEPAK_SRAM + GAMEPAK_SRAM_SIZE;
-
-pub struct GBAMemory(Memory);
-impl GBAMemory {
-    pub fn new(rom_path: &Path, bios_path: &Path) -> EmulatorCoreResult<GBAMemory> {
-        let bios_bytes = fs::read(bios_path)?;
-        let rom_bytes = fs::read(rom_path)?;
-
-        if bios_bytes.len() != BIOS_FILE_SIZE {
-            return Err(EmulatorCoreError::InvalidBiosFile(bios_path.display().to_string()));
-        }
-        if rom_bytes.len() > GAMEPAK_MAX_FILE_SIZE {
-            return Err(EmulatorCoreError::IncompatibleRom);
-        }
-
-        let memory = Memory::new(vec![
-            MemoryRegion::new(ADDRESS_START_BIOS, bios_bytes),
-            MemoryRegion::new(ADDRESS_START_WRAM_BOARD, vec![0; WRAM_ON_BOARD_SIZE]),
-            MemoryRegion::new(ADDRESS_START_WRAM_CHIP, vec![0; WRAM_ON_CHIP_SIZE]),
-            MemoryRegion::new(ADDRESS_START_IO_REGISTERS, vec![0; IO_REGISTERS_SIZE]),
-            MemoryRegion::new(ADDRESS_START_PALETTE, vec![0; PALETTE_RAM_SIZE]),
-            MemoryRegion::new(ADDRESS_START_VRAM, vec![0; VRAM_SIZE]),
-            MemoryRegion::new(ADDRESS_START_OAM, vec![0; OAM_SIZE]),
-            MemoryRegion::new(ADDRESS_START_GAMEPAK_WAIT0, rom_bytes.clone()),
-            MemoryRegion::new(ADDRESS_START_GAMEPAK_WAIT1, rom_bytes.clone()),
-            MemoryRegion::new(ADDRESS_START_GAMEPAK_WAIT2, rom_bytes),
-            // TODO May need to do a save file for the SRAM
-            MemoryRegion::new(ADDRESS_START_GAMEPAK_SRAM, vec![0; GAMEPAK_SRAM_SIZE]),
-        ]);
-
-        Ok(GBAMemory(memory))
-    }
-
-    pub fn get_cycles_for_address(&self, address: usize, access_width: AccessWidth) -> usize {
-        match address {
-            ADDRESS_START_BIOS..=ADDRESS_END_BIOS => 1,
-            // TODO WRAM on the board needs to use waitstate settings
-            ADDRESS_START_WRAM_BOARD..=ADDRESS_END_WRAM_BOARD => match access_width {
-                AccessWidth::Bit8 | AccessWidth::Bit16 => 3,
-                AccessWidth::Bit32 => 6,
-            },
-            ADDRESS_START_WRAM_CHIP..=ADDRESS_END_WRAM_CHIP => 1,
-            ADDRESS_START_IO_REGISTERS..=ADDRESS_END_IO_REGISTERS => 1,
-            // TODO Plus 1 cycle if video memory is being accessed at the same time
-            ADDRESS_START_PALETTE..=ADDRESS_END_PALETTE => match access_width {
-                AccessWidth::Bit8 | AccessWidth::Bit16 => 1,
-                AccessWidth::Bit32 => 2,
-            },
-            ADDRESS_START_VRAM..=ADDRESS_END_VRAM => match access_width {
-                AccessWidth::Bit8 | AccessWidth::Bit16 => 1,
-                AccessWidth::Bit32 => 2,
-            },
-            ADDRESS_START_OAM..=ADDRESS_END_OAM => 1,
-            // TODO All gamepak accesses need to use waitstate settings
-            ADDRESS_START_GAMEPAK_WAIT0..=ADDRESS_END_GAMEPAK_WAIT0 => match access_width {
-                AccessWidth::Bit8 | AccessWidth::Bit16 => 5,
-                AccessWidth::Bit32 => 8,
-            },
-            ADDRESS_START_GAMEPAK_WAIT1..=ADDRESS_END_GAMEPAK_WAIT1 => match access_width {
-                AccessWidth::Bit8 | AccessWidth::Bit16 => 5,
-                AccessWidth::Bit32 => 8,
-            },
-            ADDRESS_START_GAMEPAK_WAIT2..=ADDRESS_END_GAMEPAK_WAIT2 => match access_width {
-                AccessWidth::Bit8 | AccessWidth::Bit16 => 5,
-                AccessWidth::Bit32 => 8,
-            },
-            ADDRESS_START_GAMEPAK_SRAM..=ADDRESS_END_GAMEPAK_SRAM => 5,
-            _ => 0,
-        }
-    }
-}
-impl Deref for GBAMemory {
-    type Target = Memory;
-    fn deref(&self) -> &Memory { &self.0 }
-}
-impl DerefMut for GBAMemory {
-    fn deref_mut(&mut self) -> &mut Memory { &mut self.0 }
-}
-
-#[derive(Copy, Clone)]
-pub enum AccessWidth {
-    Bit8,
-    Bit16,
-    Bit32,
-}
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    fs,
+    ops::{Deref, DerefMut},
+    path::{Path},
+    rc::Rc,
+};
+use brave_emulator_common::{
+    EmulatorCoreResult, EmulatorCoreError,
+    memory::{Memory, MemoryError, MemoryRegion, MemoryResult, WritePermission},
+};
+
+use crate::{
+    bus::{AccessType, Bus},
+    display::Display,
+    interrupt::InterruptController,
+    io::IoRegisters,
+    prefetch::PrefetchBuffer,
+    wait_control::WaitControl,
+};
+
+/// The BIOS file will always be 16Kb
+const BIOS_FILE_SIZE: usize = 16 << 10;
+/// The work RAM on the board is 256KB
+const WRAM_ON_BOARD_SIZE: usize = 256 << 10;
+/// The work RAM on the chip is 32KB
+const WRAM_ON_CHIP_SIZE: usize = 32 << 10;
+/// 1KB for the IO registers
+const IO_REGISTERS_SIZE: usize = 1 << 10;
+/// 1KB for the palette RAM
+const PALETTE_RAM_SIZE: usize = 1 << 10;
+/// 96KB for the Video RAM
+const VRAM_SIZE: usize = 96 << 10;
+/// 1KB for the Object Attribute Memory
+const OAM_SIZE: usize = 1 << 10;
+/// The gamepak can be a max of 32MB
+const GAMEPAK_MAX_FILE_SIZE: usize = 32 << 20;
+/// 32KB for the SRAM
+const GAMEPAK_SRAM_SIZE: usize = 32 << 10;
+/// Each area of the bus (picked by the top byte of the address) is 16MB
+const AREA_SIZE: usize = 16 << 20;
+/// The VRAM mirrors every 128KB, where the last 32KB repeats the last 32KB of the VRAM
+const VRAM_MIRROR_SIZE: usize = 128 << 10;
+
+const ADDRESS_START_BIOS: usize = 0x0000_0000;
+// The end addresses are inclusive, and cover the mirrors
+const ADDRESS_END_BIOS: usize = ADDRESS_START_BIOS + BIOS_FILE_SIZE - 1;
+const ADDRESS_START_WRAM_BOARD: usize = 0x0200_0000;
+const ADDRESS_END_WRAM_BOARD: usize = ADDRESS_START_WRAM_BOARD + AREA_SIZE - 1;
+const ADDRESS_START_WRAM_CHIP: usize = 0x0300_0000;
+const ADDRESS_END_WRAM_CHIP: usize = ADDRESS_START_WRAM_CHIP + AREA_SIZE - 1;
+const ADDRESS_START_IO_REGISTERS: usize = 0x0400_0000;
+const ADDRESS_END_IO_REGISTERS: usize = ADDRESS_START_IO_REGISTERS + IO_REGISTERS_SIZE - 1;
+const ADDRESS_START_PALETTE: usize = 0x0500_0000;
+const ADDRESS_END_PALETTE: usize = ADDRESS_START_PALETTE + AREA_SIZE - 1;
+const ADDRESS_START_VRAM: usize = 0x0600_0000;
+const ADDRESS_END_VRAM: usize = ADDRESS_START_VRAM + AREA_SIZE - 1;
+const ADDRESS_START_OAM: usize = 0x0700_0000;
+const ADDRESS_END_OAM: usize = ADDRESS_START_OAM + AREA_SIZE - 1;
+const ADDRESS_START_GAMEPAK_WAIT0: usize = 0x0800_0000;
+const ADDRESS_END_GAMEPAK_WAIT0: usize = ADDRESS_START_GAMEPAK_WAIT0 + GAMEPAK_MAX_FILE_SIZE - 1;
+const ADDRESS_START_GAMEPAK_WAIT1: usize = 0x0A00_0000;
+const ADDRESS_END_GAMEPAK_WAIT1: usize = ADDRESS_START_GAMEPAK_WAIT1 + GAMEPAK_MAX_FILE_SIZE - 1;
+const ADDRESS_START_GAMEPAK_WAIT2: usize = 0x0C00_0000;
+const ADDRESS_END_GAMEPAK_WAIT2: usize = ADDRESS_START_GAMEPAK_WAIT2 + GAMEPAK_MAX_FILE_SIZE - 1;
+const ADDRESS_START_GAMEPAK_SRAM: usize = 0x0E00_0000;
+/// The SRAM mirrors through both of the last areas
+const ADDRESS_END_GAMEPAK_SRAM: usize = ADDRESS_START_GAMEPAK_SRAM + 2 * AREA_SIZE - 1;
+
+pub struct GBAMemory {
+    memory: Memory,
+    /// Shared with the IO registers, and decides when the video memory is busy
+    display: Rc<RefCell<Display>>,
+    /// Shared with the IO registers, so that writes reach it
+    interrupts: Rc<RefCell<InterruptController>>,
+    /// Shared with the IO registers, so that the timing follows WAITCNT
+    wait_control: Rc<RefCell<WaitControl>>,
+    prefetch: PrefetchBuffer,
+    /// What gets read from addresses that nothing answers, which is the last prefetched opcode
+    open_bus: u32,
+}
+impl GBAMemory {
+    pub fn new(rom_path: &Path, bios_path: &Path) -> EmulatorCoreResult<GBAMemory> {
+        let bios_bytes = fs::read(bios_path)?;
+        let rom_bytes = fs::read(rom_path)?;
+
+        if bios_bytes.len() != BIOS_FILE_SIZE {
+            return Err(EmulatorCoreError::InvalidBiosFile(bios_path.display().to_string()));
+        }
+        GBAMemory::from_bytes(rom_bytes, bios_bytes)
+    }
+
+    /// Maps the ROM and the BIOS (which needs to be the right size already)
+    pub fn from_bytes(rom_bytes: Vec<u8>, bios_bytes: Vec<u8>) -> EmulatorCoreResult<GBAMemory> {
+        if rom_bytes.len() > GAMEPAK_MAX_FILE_SIZE {
+            return Err(EmulatorCoreError::IncompatibleRom);
+        }
+
+        let rom_size = rom_bytes.len();
+        let rom_bytes: Rc<[u8]> = rom_bytes.into();
+        let interrupts = Rc::new(RefCell::new(InterruptController::new()));
+        let wait_control = Rc::new(RefCell::new(WaitControl::new()));
+        let display = Rc::new(RefCell::new(Display::new(Rc::clone(&interrupts))));
+        let io_registers = IoRegisters::new(IO_REGISTERS_SIZE, Rc::clone(&display),
+            Rc::clone(&interrupts), Rc::clone(&wait_control));
+
+        let mut regions = vec![
+            MemoryRegion::new(ADDRESS_START_BIOS, bios_bytes)
+                .with_write_permission(WritePermission::Ignored),
+            MemoryRegion::open_bus(ADDRESS_END_BIOS + 1,
+                ADDRESS_START_WRAM_BOARD - (ADDRESS_END_BIOS + 1)),
+            MemoryRegion::new(ADDRESS_START_WRAM_BOARD, vec![0; WRAM_ON_BOARD_SIZE])
+                .mirrored(WRAM_ON_BOARD_SIZE, AREA_SIZE),
+            MemoryRegion::new(ADDRESS_START_WRAM_CHIP, vec![0; WRAM_ON_CHIP_SIZE])
+                .mirrored(WRAM_ON_CHIP_SIZE, AREA_SIZE),
+            MemoryRegion::with_handler(ADDRESS_START_IO_REGISTERS, IO_REGISTERS_SIZE,
+                Box::new(io_registers)),
+            MemoryRegion::open_bus(ADDRESS_END_IO_REGISTERS + 1,
+                ADDRESS_START_PALETTE - (ADDRESS_END_IO_REGISTERS + 1)),
+            MemoryRegion::new(ADDRESS_START_PALETTE, vec![0; PALETTE_RAM_SIZE])
+                .mirrored(PALETTE_RAM_SIZE, AREA_SIZE),
+            MemoryRegion::new(ADDRESS_START_VRAM, vec![0; VRAM_SIZE])
+                .mirrored(VRAM_MIRROR_SIZE, AREA_SIZE),
+            MemoryRegion::new(ADDRESS_START_OAM, vec![0; OAM_SIZE])
+                .mirrored(OAM_SIZE, AREA_SIZE),
+            // The ROM is the same in all of the waitstate areas
+            MemoryRegion::shared(ADDRESS_START_GAMEPAK_WAIT0, Rc::clone(&rom_bytes)),
+            MemoryRegion::shared(ADDRESS_START_GAMEPAK_WAIT1, Rc::clone(&rom_bytes)),
+            MemoryRegion::shared(ADDRESS_START_GAMEPAK_WAIT2, rom_bytes),
+            // TODO May need to do a save file for the SRAM
+            MemoryRegion::new(ADDRESS_START_GAMEPAK_SRAM, vec![0; GAMEPAK_SRAM_SIZE])
+                .mirrored(GAMEPAK_SRAM_SIZE, 2 * AREA_SIZE),
+        ];
+        // Nothing answers past the end of the ROM
+        if rom_size < GAMEPAK_MAX_FILE_SIZE {
+            let open_size = GAMEPAK_MAX_FILE_SIZE - rom_size;
+            for &start_address in &[
+                ADDRESS_START_GAMEPAK_WAIT0,
+                ADDRESS_START_GAMEPAK_WAIT1,
+                ADDRESS_START_GAMEPAK_WAIT2,
+            ] {
+                regions.push(MemoryRegion::open_bus(start_address + rom_size, open_size));
+            }
+        }
+
+        Ok(GBAMemory {
+            memory: Memory::new(regions),
+            display,
+            interrupts,
+            wait_control,
+            prefetch: PrefetchBuffer::new(),
+            open_bus: 0,
+        })
+    }
+
+    pub fn display_mut(&self) -> RefMut<'_, Display> { self.display.borrow_mut() }
+    pub fn interrupts(&self) -> Ref<'_, InterruptController> { self.interrupts.borrow() }
+    // For the hardware that raises its interrupts through the memory, like the timers and DMA
+    #[allow(dead_code)]
+    pub fn interrupts_mut(&self) -> RefMut<'_, InterruptController> { self.interrupts.borrow_mut() }
+
+    /// Fetches an instruction, giving back the opcode and the cycles it took.
+    /// Fetches from the gamepak can come out of the prefetch buffer.
+    pub fn fetch_opcode(&mut self, address: u32, is_thumb: bool, access_type: AccessType)
+    -> MemoryResult<(u32, usize)> {
+        let size = if is_thumb { 2 } else { 4 };
+        let address = (address as usize) & !(size - 1);
+        let opcode = self.read_value(address, size)?;
+        let mut cycles = self.access_cycles(address, size, access_type);
+
+        if let Some(area) = gamepak_area(address) {
+            let wait_control = self.wait_control.borrow();
+            if wait_control.is_prefetch_enabled() {
+                match self.prefetch.take(address as u32, size / 2) {
+                    Some(prefetch_cycles) => cycles = prefetch_cycles,
+                    None => self.prefetch.restart((address + size) as u32,
+                        wait_control.gamepak_cycles(area, AccessType::Sequential)),
+                }
+            } else {
+                self.prefetch.stop();
+            }
+        }
+
+        // The opcode stays on the bus, and a Thumb opcode shows up in both halves of it
+        self.open_bus = if is_thumb { opcode * 0x0001_0001 } else { opcode };
+        Ok((opcode, cycles))
+    }
+
+    /// The cycles for the opcode fetch that a branch throws away, which carried on from the last
+    pub fn discarded_fetch_cycles(&self, address: u32, is_thumb: bool) -> usize {
+        let size = if is_thumb { 2 } else { 4 };
+        self.access_cycles((address as usize) & !(size - 1), size, AccessType::Sequential)
+    }
+
+    /// Lets the prefetch buffer read ahead while the CPU spends the cycles executing
+    pub fn run_prefetch(&mut self, cycles: usize) {
+        self.prefetch.run(cycles);
+    }
+
+    /// Reads the little endian bytes at the address, which needs to be aligned by the caller
+    fn read_value(&self, address: usize, size: usize) -> MemoryResult<u32> {
+        let mut bytes = [0; 4];
+        // Plain memory is read straight out, but something like the IO registers needs the handler
+        match self.memory.bytes(address, size) {
+            Some(source) => bytes[..size].copy_from_slice(source),
+            None => match self.read(address, &mut bytes[..size]) {
+                Ok(()) => {},
+                Err(MemoryError::NoMatchingRegion) |
+                Err(MemoryError::OpenBus) => return Ok(self.read_open_bus(address, size)),
+                // Like the end of an odd sized ROM, where the rest of the bytes are open bus
+                Err(MemoryError::CrossRegionAccess) => {
+                    for (index, byte) in bytes[..size].iter_mut().enumerate() {
+                        *byte = self.read_value(address + index, 1)? as u8;
+                    }
+                },
+                Err(e) => return Err(e),
+            },
+        }
+        Ok(u32::from_le_bytes(bytes))
+    }
+    /// Writes the bottom bytes of the value in little endian.
+    /// The address needs to be aligned by the caller.
+    fn write_value(&mut self, address: usize, size: usize, value: u32) -> MemoryResult<()> {
+        let bytes = value.to_le_bytes();
+        match self.memory.bytes_mut(address, size) {
+            Some(destination) => {
+                destination.copy_from_slice(&bytes[..size]);
+                Ok(())
+            },
+            // Writes to addresses that nothing answers go nowhere
+            None => match self.write(address, &bytes[..size]) {
+                Err(MemoryError::NoMatchingRegion) => Ok(()),
+                result => result,
+            },
+        }
+    }
+
+    /// The cycles that an access of the size (in bytes) takes at the address
+    fn access_cycles(&self, address: usize, size: usize, access_type: AccessType) -> usize {
+        let is_word = size == 4;
+        // A word needs 2 accesses on the 16 bit buses
+        let accesses_16_bit = if is_word { 2 } else { 1 };
+        match address {
+            ADDRESS_START_BIOS..=ADDRESS_END_BIOS => 1,
+            // TODO WRAM on the board has its own waitstate setting (0x04000800)
+            ADDRESS_START_WRAM_BOARD..=ADDRESS_END_WRAM_BOARD => 3 * accesses_16_bit,
+            ADDRESS_START_WRAM_CHIP..=ADDRESS_END_WRAM_CHIP => 1,
+            ADDRESS_START_IO_REGISTERS..=ADDRESS_END_IO_REGISTERS => 1,
+            ADDRESS_START_PALETTE..=ADDRESS_END_PALETTE =>
+                accesses_16_bit + self.video_contention_cycles(),
+            ADDRESS_START_VRAM..=ADDRESS_END_VRAM =>
+                accesses_16_bit + self.video_contention_cycles(),
+            ADDRESS_START_OAM..=ADDRESS_END_OAM => 1 + self.video_contention_cycles(),
+            ADDRESS_START_GAMEPAK_WAIT0..=ADDRESS_END_GAMEPAK_WAIT0 =>
+                self.gamepak_cycles(0, is_word, access_type),
+            ADDRESS_START_GAMEPAK_WAIT1..=ADDRESS_END_GAMEPAK_WAIT1 =>
+                self.gamepak_cycles(1, is_word, access_type),
+            ADDRESS_START_GAMEPAK_WAIT2..=ADDRESS_END_GAMEPAK_WAIT2 =>
+                self.gamepak_cycles(2, is_word, access_type),
+            ADDRESS_START_GAMEPAK_SRAM..=ADDRESS_END_GAMEPAK_SRAM =>
+                self.wait_control.borrow().sram_cycles(),
+            // Nothing answers, but the bus still takes a cycle
+            _ => 1,
+        }
+    }
+
+    /// The gamepak is on a 16 bit bus, so a word is a halfword access followed by a sequential one
+    fn gamepak_cycles(&self, area: usize, is_word: bool, access_type: AccessType) -> usize {
+        let wait_control = self.wait_control.borrow();
+        let cycles = wait_control.gamepak_cycles(area, access_type);
+        if is_word {
+            cycles + wait_control.gamepak_cycles(area, AccessType::Sequential)
+        } else {
+            cycles
+        }
+    }
+
+    /// The extra cycle for the video memory while the display is drawing with it
+    fn video_contention_cycles(&self) -> usize {
+        if self.display.borrow().is_drawing() { 1 } else { 0 }
+    }
+
+    /// If the display is using OAM, so writes to it don't get through
+    fn is_oam_locked(&self, address: usize) -> bool {
+        match address {
+            ADDRESS_START_OAM..=ADDRESS_END_OAM => self.display.borrow().is_oam_locked(),
+            _ => false,
+        }
+    }
+
+    /// The part of the open bus value that an access of the size (in bytes) would see
+    fn read_open_bus(&self, address: usize, size: usize) -> u32 {
+        let value = self.open_bus >> ((address & 0b11) * 8);
+        match size {
+            1 => value & 0xFF,
+            2 => value & 0xFFFF,
+            _ => value,
+        }
+    }
+
+    /// A data access to the gamepak takes the bus away from the prefetch buffer
+    fn stop_prefetch_for(&mut self, address: usize) {
+        if gamepak_area(address).is_some() {
+            self.prefetch.stop();
+        }
+    }
+
+    /// Does a read of the size (in bytes), after forcing the alignment
+    fn read_sized(&mut self, address: u32, size: usize, access_type: AccessType)
+    -> MemoryResult<(u32, usize)> {
+        let address = (address as usize) & !(size - 1);
+        self.stop_prefetch_for(address);
+        let value = self.read_value(address, size)?;
+        Ok((value, self.access_cycles(address, size, access_type)))
+    }
+    /// Does a write of the size (in bytes), after forcing the alignment
+    fn write_sized(&mut self, address: u32, size: usize, value: u32, access_type: AccessType)
+    -> MemoryResult<usize> {
+        let address = (address as usize) & !(size - 1);
+        self.stop_prefetch_for(address);
+        if !self.is_oam_locked(address) {
+            self.write_value(address, size, value)?;
+        }
+        Ok(self.access_cycles(address, size, access_type))
+    }
+}
+impl Bus for GBAMemory {
+    fn read8(&mut self, address: u32, access_type: AccessType) -> MemoryResult<(u8, usize)> {
+        let (value, cycles) = self.read_sized(address, 1, access_type)?;
+        Ok((value as u8, cycles))
+    }
+    fn read16(&mut self, address: u32, access_type: AccessType) -> MemoryResult<(u16, usize)> {
+        let (value, cycles) = self.read_sized(address, 2, access_type)?;
+        Ok((value as u16, cycles))
+    }
+    fn read32(&mut self, address: u32, access_type: AccessType) -> MemoryResult<(u32, usize)> {
+        self.read_sized(address, 4, access_type)
+    }
+
+    fn write8(&mut self, address: u32, value: u8, access_type: AccessType)
+    -> MemoryResult<usize> {
+        self.write_sized(address, 1, value as u32, access_type)
+    }
+    fn write16(&mut self, address: u32, value: u16, access_type: AccessType)
+    -> MemoryResult<usize> {
+        self.write_sized(address, 2, value as u32, access_type)
+    }
+    fn write32(&mut self, address: u32, value: u32, access_type: AccessType)
+    -> MemoryResult<usize> {
+        self.write_sized(address, 4, value, access_type)
+    }
+}
+impl Deref for GBAMemory {
+    type Target = Memory;
+    fn deref(&self) -> &Memory { &self.memory }
+}
+impl DerefMut for GBAMemory {
+    fn deref_mut(&mut self) -> &mut Memory { &mut self.memory }
+}
+
+/// Which of the gamepak waitstate areas (0, 1 or 2) the address is in
+fn gamepak_area(address: usize) -> Option<usize> {
+    match address {
+        ADDRESS_START_GAMEPAK_WAIT0..=ADDRESS_END_GAMEPAK_WAIT0 => Some(0),
+        ADDRESS_START_GAMEPAK_WAIT1..=ADDRESS_END_GAMEPAK_WAIT1 => Some(1),
+        ADDRESS_START_GAMEPAK_WAIT2..=ADDRESS_END_GAMEPAK_WAIT2 => Some(2),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn odd_sized_rom_ends_in_open_bus() {
+        let rom = vec![0x11, 0x22, 0x33];
+        let mut memory = GBAMemory::from_bytes(rom, vec![0; BIOS_FILE_SIZE]).unwrap();
+        memory.open_bus = 0xAABB_CCDD;
+
+        let (value, _) = memory.read32(0x0800_0000, AccessType::NonSequential).unwrap();
+        assert_eq!(value, 0xAA33_2211);
+        let (value, _) = memory.read16(0x0A00_0002, AccessType::NonSequential).unwrap();
+        assert_eq!(value, 0xAA33);
+        let (opcode, _) = memory.fetch_opcode(0x0800_0002, true, AccessType::Sequential).unwrap();
+        assert_eq!(opcode, 0xAA33);
+    }
+}
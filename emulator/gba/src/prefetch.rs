@@ -0,0 +1,81 @@
+/// The buffer holds up to 8 halfwords
+const CAPACITY: usize = 8;
+
+/// The gamepak prefetch unit, which reads sequential halfwords ahead of the CPU
+/// while the gamepak bus isn't being used for anything else
+pub struct PrefetchBuffer {
+    /// If it's reading ahead, which only starts after an instruction fetch from the gamepak
+    active: bool,
+    /// The address of the oldest halfword in the buffer (or the one being read if it's empty)
+    head_address: u32,
+    /// How many halfwords are ready
+    count: usize,
+    /// The cycles spent so far on reading the halfword after the ready ones
+    progress: usize,
+    /// The cycles it takes to read each halfword (a sequential access)
+    halfword_cycles: usize,
+}
+impl PrefetchBuffer {
+    pub fn new() -> PrefetchBuffer {
+        PrefetchBuffer {
+            active: false,
+            head_address: 0,
+            count: 0,
+            progress: 0,
+            halfword_cycles: 0,
+        }
+    }
+
+    /// Throws away the buffer and stops reading ahead (when the bus gets used for data)
+    pub fn stop(&mut self) {
+        self.active = false;
+        self.count = 0;
+        self.progress = 0;
+    }
+
+    /// Starts reading ahead from the address, after an instruction fetch that missed the buffer
+    pub fn restart(&mut self, address: u32, halfword_cycles: usize) {
+        self.active = true;
+        self.head_address = address;
+        self.count = 0;
+        self.progress = 0;
+        self.halfword_cycles = halfword_cycles;
+    }
+
+    /// Lets the prefetch unit use the gamepak bus while the CPU spends the cycles elsewhere
+    pub fn run(&mut self, cycles: usize) {
+        if !self.active || self.count == CAPACITY {
+            return;
+        }
+
+        self.progress += cycles;
+        let read_count = (self.progress / self.halfword_cycles).min(CAPACITY - self.count);
+        self.count += read_count;
+        self.progress = if self.count == CAPACITY {
+            0
+        } else {
+            self.progress - read_count * self.halfword_cycles
+        };
+    }
+
+    /// Takes the halfwords of an instruction fetch out of the front of the buffer.
+    /// Gives back the cycles the fetch took, or nothing if the address isn't next in the buffer.
+    pub fn take(&mut self, address: u32, halfwords: usize) -> Option<usize> {
+        if !self.active || address != self.head_address {
+            return None;
+        }
+
+        self.head_address = address.wrapping_add(halfwords as u32 * 2);
+        if self.count >= halfwords {
+            self.count -= halfwords;
+            return Some(1);
+        }
+
+        // The rest of the instruction is still being read, so the fetch waits for it
+        let missing = halfwords - self.count;
+        let cycles = missing * self.halfword_cycles - self.progress;
+        self.count = 0;
+        self.progress = 0;
+        Some(cycles)
+    }
+}
@@ -0,0 +1,61 @@
+use crate::bus::AccessType;
+
+/// The waitstates for the first access (SRAM and every non-sequential gamepak access)
+const NON_SEQUENTIAL_WAITSTATES: [usize; 4] = [4, 3, 2, 8];
+/// The waitstates for a sequential access to each of the gamepak areas, when the bit is clear
+const SEQUENTIAL_WAITSTATES: [usize; 3] = [2, 4, 8];
+/// Turns on the gamepak prefetch buffer
+const PREFETCH_BIT: u16 = 0b01000000_00000000;
+/// The top bit (gamepak type) can't be written
+const WRITABLE_BITS: u16 = 0b01011111_11111111;
+
+/// The WAITCNT register (0x04000204), which controls the timing of the gamepak
+pub struct WaitControl {
+    value: u16,
+}
+impl WaitControl {
+    pub fn new() -> WaitControl {
+        WaitControl { value: 0 }
+    }
+
+    /// Reads a byte, where the offset is from the start of WAITCNT
+    pub fn read_byte(&self, offset: usize) -> u8 {
+        match offset {
+            0 => self.value as u8,
+            1 => (self.value >> 8) as u8,
+            // The top half of the word is unused
+            _ => 0,
+        }
+    }
+    /// Writes a byte, where the offset is from the start of WAITCNT
+    pub fn write_byte(&mut self, offset: usize, value: u8) {
+        match offset {
+            0 => self.value = ((self.value & 0xFF00) | value as u16) & WRITABLE_BITS,
+            1 => self.value = ((self.value & 0x00FF) | (value as u16) << 8) & WRITABLE_BITS,
+            _ => {},
+        }
+    }
+
+    /// The cycles for a 16 bit access to one of the gamepak areas (0, 1 or 2)
+    pub fn gamepak_cycles(&self, area: usize, access_type: AccessType) -> usize {
+        // Each area has 2 bits for the non-sequential waitstates and 1 for the sequential ones
+        let shift = 2 + area * 3;
+        let waitstates = match access_type {
+            AccessType::NonSequential =>
+                NON_SEQUENTIAL_WAITSTATES[((self.value >> shift) & 0b11) as usize],
+            AccessType::Sequential if (self.value >> (shift + 2)) & 1 == 1 => 1,
+            AccessType::Sequential => SEQUENTIAL_WAITSTATES[area],
+        };
+        1 + waitstates
+    }
+
+    /// The cycles for an access to the SRAM, which doesn't have sequential accesses
+    pub fn sram_cycles(&self) -> usize {
+        1 + NON_SEQUENTIAL_WAITSTATES[(self.value & 0b11) as usize]
+    }
+
+    /// If the gamepak prefetch buffer is turned on
+    pub fn is_prefetch_enabled(&self) -> bool {
+        self.value & PREFETCH_BIT == PREFETCH_BIT
+    }
+}